@@ -0,0 +1,18 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate fury3clone_fuzz;
+
+use fury3clone_fuzz::datafile::Model;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(model) = Model::from_bin_file(data)
+    {
+        // All face indexes must be valid
+        for f in &model.faces {
+            for &v in &f.v {
+                model.vertex_position(v);
+            }
+        }
+    }
+});
@@ -0,0 +1,16 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate fury3clone_fuzz;
+
+use fury3clone_fuzz::terrain::ByteGrid;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(grid) = ByteGrid::from_reader(data)
+    {
+        let d = grid.dim() as isize;
+        for &(x, z) in &[ (0, 0), (-1, -1), (d, d), (d - 1, 0), (0, d + 5) ] {
+            grid.get(x, z);
+        }
+    }
+});
@@ -0,0 +1,16 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate fury3clone_fuzz;
+
+use fury3clone_fuzz::datafile::EntityFile;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(file) = EntityFile::from_file(data)
+    {
+        // Anything that parses must write out exactly as it was read
+        let mut out = Vec::new();
+        file.write(&mut out).unwrap();
+        assert!(out[..] == data[..]);
+    }
+});
@@ -0,0 +1,10 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate fury3clone_fuzz;
+
+use fury3clone_fuzz::datafile::Level;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Level::from_file(data);
+});
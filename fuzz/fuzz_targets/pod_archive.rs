@@ -0,0 +1,24 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate fury3clone_fuzz;
+
+use std::io::Read;
+use fury3clone_fuzz::datafile::PodArchive;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(pod) = PodArchive::from_bytes(data.to_vec())
+    {
+        for name in pod.file_names()
+        {
+            if let Ok(mut fh) = pod.open_file(&name) {
+                let mut buf = Vec::new();
+                let _ = fh.read_to_end(&mut buf);
+            }
+            if let Some(p) = name.find('\\') {
+                let _ = pod.dir_file_data(&name[..p], &name[p+1..]);
+            }
+        }
+        let _ = pod.open_dir_file("DATA", "EGYPT.RAW");
+    }
+});
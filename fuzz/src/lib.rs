@@ -0,0 +1,15 @@
+//!
+//! Parser modules from the main crate, for the fuzz targets (the main crate is a binary, so can't be depended on)
+//!
+#![allow(dead_code)]
+#[macro_use]
+extern crate log;
+extern crate byteorder;
+extern crate memmap;
+
+#[path = "../../src/datafile/mod.rs"]
+pub mod datafile;
+#[path = "../../src/world_units.rs"]
+pub mod world_units;
+#[path = "../../src/terrain.rs"]
+pub mod terrain;
@@ -0,0 +1,255 @@
+//!
+//! Cache of loaded models, decoded textures, palettes and materials, keyed by data path
+//!
+//! Handles are shared between all users (Amethyst handles are reference counted), and the cache keeps one reference to
+//! each until `clear` is called when the level is unloaded.
+use std::collections::HashMap;
+use std::sync::Arc;
+use amethyst::prelude::*;
+use amethyst::renderer as a_renderer;
+
+use super::{BoxError, DataPath, DataPathBuf, PodFiles};
+
+pub(crate) struct AssetCache
+{
+    pods: Arc<PodFiles>,
+    meshes: HashMap<DataPathBuf, a_renderer::MeshHandle>,
+    /// Decoded RGBA textures (size and pixels) for the level's texture atlas, keyed by (texture, palette) as the same
+    /// `.RAW` can be coloured with different palettes
+    texture_pixels: HashMap<(DataPathBuf, DataPathBuf), Arc<(usize, Vec<u8>)>>,
+    /// Missing palettes are common (most textures use the level palette), so failed lookups are cached too
+    palettes: HashMap<DataPathBuf, Option<Arc<Vec<u8>>>>,
+    /// Solid colour materials, keyed by the bits of the colour
+    materials: HashMap<[u32; 4], a_renderer::Material>,
+}
+
+impl AssetCache
+{
+    pub(crate) fn new(pods: Arc<PodFiles>) -> AssetCache
+    {
+        AssetCache {
+            pods: pods,
+            meshes: HashMap::new(),
+            texture_pixels: HashMap::new(),
+            palettes: HashMap::new(),
+            materials: HashMap::new(),
+        }
+    }
+
+    /// Release all cached handles (assets are freed once entities stop using them)
+    pub(crate) fn clear(&mut self)
+    {
+        debug!("Releasing {} meshes, {} textures, {} palettes, {} materials",
+            self.meshes.len(), self.texture_pixels.len(), self.palettes.len(), self.materials.len());
+        self.meshes.clear();
+        self.texture_pixels.clear();
+        self.palettes.clear();
+        self.materials.clear();
+    }
+
+    /// Mesh for a `.BIN` model
+    pub(crate) fn mesh(&mut self, world: &World, path: DataPath) -> Result<a_renderer::MeshHandle, BoxError>
+    {
+        let key = path.to_buf();
+        if let Some(h) = self.meshes.get(&key) {
+            return Ok(h.clone());
+        }
+        let h = super::upload_mesh(world, self.pods.decode_model(path)?);
+        self.meshes.insert(key, h.clone());
+        Ok(h)
+    }
+
+    /// Load a mesh again after its file has changed, returning the previously cached handle (if any) and the new one
+    ///
+    /// The old handle stays cached if loading fails.
+    pub(crate) fn reload_mesh(&mut self, world: &World, path: DataPath) -> Result<(Option<a_renderer::MeshHandle>, a_renderer::MeshHandle), BoxError>
+    {
+        let old = self.meshes.remove(&path.to_buf());
+        match self.mesh(world, path)
+        {
+        Ok(new) => Ok( (old, new) ),
+        Err(e) => {
+            if let Some(h) = old {
+                self.meshes.insert(path.to_buf(), h);
+            }
+            Err(e)
+            },
+        }
+    }
+
+    /// Decode any of the listed models (in MODELS) that aren't cached yet on Amethyst's thread pool
+    ///
+    /// Failures are only logged, the error is returned again by `mesh`.
+    pub(crate) fn preload_models(&mut self, world: &World, names: &[String])
+    {
+        let mut names: Vec<_> = names.iter()
+            .filter(|n| !self.meshes.contains_key(&datapath!(Game, Models, n).to_buf()))
+            .cloned()
+            .collect();
+        names.sort();
+        names.dedup();
+        let decoded = {
+            let pool = world.read_resource::<Arc<::amethyst::core::rayon::ThreadPool>>();
+            super::load_parallel(&pool, &self.pods, &names, |pods, name| pods.decode_model(datapath!(Game, Models, name)))
+            };
+        for (name, rv) in names.iter().zip(decoded)
+        {
+            match rv
+            {
+            Ok(data) => { self.meshes.insert(datapath!(Game, Models, name).to_buf(), super::upload_mesh(world, data)); },
+            Err(e) => warn!("Unable to load model {:?}: {}", name, e),
+            }
+        }
+    }
+
+    /// Size and RGBA pixels of paletted `.RAW` files, each given as (texture, palette)
+    ///
+    /// Any that aren't cached yet are decoded on Amethyst's thread pool.
+    pub(crate) fn texture_rgba(&mut self, world: &World, textures: &[(DataPath, DataPath)]) -> Result<Vec<Arc<(usize, Vec<u8>)>>, BoxError>
+    {
+        let keys: Vec<_> = textures.iter().map(|&(t, p)| (t.to_buf(), p.to_buf())).collect();
+        let mut missing = Vec::new();
+        for (key, &(_, palette)) in keys.iter().zip(textures)
+        {
+            if !self.texture_pixels.contains_key(key) && !missing.iter().any(|&(ref k, _)| k == key) {
+                missing.push( (key.clone(), self.palette(palette)?) );
+            }
+        }
+        let decoded = {
+            let pool = world.read_resource::<Arc<::amethyst::core::rayon::ThreadPool>>();
+            super::load_parallel(&pool, &self.pods, &missing, |pods, &(ref key, ref palette)| pods.decode_texture(key.0.as_path(), palette))
+            };
+        for ((key, _), rv) in missing.into_iter().zip(decoded)
+        {
+            let data = rv.map_err(|e| format!("{:?}: {}", key.0, e))?;
+            self.texture_pixels.insert(key, Arc::new(data));
+        }
+        Ok( keys.iter().map(|k| self.texture_pixels[k].clone()).collect() )
+    }
+
+    /// Drop a cached texture (with any palette, e.g. after its file changes), so it's loaded again when next used
+    pub(crate) fn forget_texture(&mut self, path: DataPath)
+    {
+        let path = path.to_buf();
+        self.texture_pixels.retain(|k, _| k.0 != path);
+    }
+
+    /// 256 entry RGB palette (`.ACT`)
+    pub(crate) fn palette(&mut self, path: DataPath) -> Result<Arc<Vec<u8>>, BoxError>
+    {
+        match self.find_palette(path)?
+        {
+        Some(v) => Ok(v),
+        None => Err(format!("Palette {:?} not found", path).into()),
+        }
+    }
+
+    /// 256 entry RGB palette (`.ACT`), or `None` if there's no such file
+    ///
+    /// Only a missing file gives `None`, a palette that can't be read is an error (and isn't cached).
+    pub(crate) fn find_palette(&mut self, path: DataPath) -> Result<Option<Arc<Vec<u8>>>, BoxError>
+    {
+        let key = path.to_buf();
+        if let Some(v) = self.palettes.get(&key) {
+            return Ok(v.clone());
+        }
+        let rv = match self.pods.load_palette(path)
+            {
+            Ok(v) => Some(Arc::new(v)),
+            Err(e) => match e.downcast_ref::<::std::io::Error>().map(|e| e.kind())
+                {
+                Some(::std::io::ErrorKind::NotFound) => None,
+                _ => return Err(format!("Palette {:?}: {}", path, e).into()),
+                },
+            };
+        self.palettes.insert(key, rv.clone());
+        Ok(rv)
+    }
+
+    /// Drop a cached palette and the textures coloured with it (e.g. after its file changes), so they're read again when
+    /// next used
+    pub(crate) fn forget_palette(&mut self, path: DataPath)
+    {
+        let path = path.to_buf();
+        self.texture_pixels.retain(|k, _| k.1 != path);
+        self.palettes.remove(&path);
+    }
+
+    /// Untextured material with the given colour
+    pub(crate) fn solid_material(&mut self, world: &World, colour: [f32; 4]) -> a_renderer::Material
+    {
+        let key = [colour[0].to_bits(), colour[1].to_bits(), colour[2].to_bits(), colour[3].to_bits()];
+        if let Some(m) = self.materials.get(&key) {
+            return m.clone();
+        }
+        let tex_storage = world.read_resource();
+        let mat_defaults = world.read_resource::<a_renderer::MaterialDefaults>();
+        let loader = world.read_resource::<::amethyst::assets::Loader>();
+        let m = a_renderer::Material {
+            albedo: loader.load_from_data(colour.into(), (), &tex_storage),
+            emission: loader.load_from_data([0.0, 0.0, 0.0, 1.0].into(), (), &tex_storage),
+            ..mat_defaults.0.clone()
+            };
+        self.materials.insert(key, m.clone());
+        m
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// Cache over the synthetic archives, with an (initially empty) override directory
+    fn cache(name: &str) -> (AssetCache, ::std::path::PathBuf)
+    {
+        let dir = ::std::env::temp_dir().join(format!("fury3clone-{}-{}", name, ::std::process::id()));
+        ::std::fs::create_dir_all(dir.join("ART")).unwrap();
+        ::std::fs::create_dir_all(dir.join("MODELS")).unwrap();
+        let pods = PodFiles { overrides: Some(dir.clone()), ..PodFiles::synthetic() };
+        (AssetCache::new(Arc::new(pods)), dir)
+    }
+
+    /// Cached assets are returned again without reading their file (which is deleted between the calls)
+    #[test]
+    fn cached_without_reading()
+    {
+        use amethyst::assets::{AssetStorage, Loader};
+        let (mut cache, dir) = cache("asset-cache");
+        let pool = Arc::new(::amethyst::core::rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap());
+        let mut world = World::new();
+        world.add_resource(Loader::new(&dir, pool));
+        world.add_resource(AssetStorage::<a_renderer::Mesh>::new());
+
+        ::std::fs::write(dir.join("ART/TEST.ACT"), ::datafile::synth::palette_act([10, 20, 30])).unwrap();
+        ::std::fs::write(dir.join("MODELS/TEST.BIN"), ::datafile::synth::model_bin(10)).unwrap();
+        let palette = cache.palette(datapath!(Game, Art, "TEST.ACT")).unwrap();
+        let mesh = cache.mesh(&world, datapath!(Game, Models, "TEST.BIN")).unwrap();
+        ::std::fs::remove_file(dir.join("ART/TEST.ACT")).unwrap();
+        ::std::fs::remove_file(dir.join("MODELS/TEST.BIN")).unwrap();
+
+        assert!(Arc::ptr_eq(&palette, &cache.palette(datapath!(Game, Art, "test.act")).unwrap()));
+        assert!(mesh == cache.mesh(&world, datapath!(Game, Models, "TEST.BIN")).unwrap());
+        // Once cleared, they're read again (and are now missing)
+        cache.clear();
+        assert!(cache.palette(datapath!(Game, Art, "TEST.ACT")).is_err());
+        assert!(cache.mesh(&world, datapath!(Game, Models, "TEST.BIN")).is_err());
+        ::std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A missing palette is cached as missing, but one that can't be read is an error each time
+    #[test]
+    fn missing_and_bad_palettes()
+    {
+        let (mut cache, dir) = cache("asset-cache-palettes");
+        assert!(cache.find_palette(datapath!(Game, Art, "MISSING.ACT")).unwrap().is_none());
+        ::std::fs::write(dir.join("ART/MISSING.ACT"), ::datafile::synth::palette_act([10, 20, 30])).unwrap();
+        assert!(cache.find_palette(datapath!(Game, Art, "MISSING.ACT")).unwrap().is_none());
+
+        ::std::fs::write(dir.join("ART/SHORT.ACT"), vec![0; 100]).unwrap();
+        assert!(cache.find_palette(datapath!(Game, Art, "SHORT.ACT")).is_err());
+        ::std::fs::write(dir.join("ART/SHORT.ACT"), ::datafile::synth::palette_act([10, 20, 30])).unwrap();
+        assert_eq!(cache.find_palette(datapath!(Game, Art, "SHORT.ACT")).unwrap().map(|v| v.len()), Some(256 * 3));
+        ::std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,304 @@
+//!
+//! Camera modes (chase, cockpit, orbit-inspect and free-fly) and the system that switches between them
+//!
+use amethyst::ecs;
+use amethyst::ecs::Join;
+use amethyst::core::transform::Transform;
+use amethyst::core::cgmath::{Deg, Matrix4, Point3, Vector3, SquareMatrix};
+use amethyst::renderer as a_renderer;
+
+use controls;
+use player::PlayerShip;
+use entity_state::EntityType;
+
+/// Chase camera distance behind the ship, and height above it
+const CHASE_DISTANCE: f32 = 1.0;
+const CHASE_HEIGHT: f32 = 0.3;
+/// Cockpit eye height above the ship's origin
+const COCKPIT_HEIGHT: f32 = 0.05;
+/// Orbit camera distance from, and height above, the inspected entity
+const ORBIT_DISTANCE: f32 = 0.8;
+const ORBIT_HEIGHT: f32 = 0.3;
+/// Orbit speed (degrees per second)
+const ORBIT_RATE: f32 = 30.;
+/// Time taken to blend between modes (seconds)
+const TRANSITION_TIME: f32 = 0.5;
+
+/// Active camera mode (a resource, also used by other systems to decide who gets the controls)
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum CameraMode
+{
+    Chase,
+    Cockpit,
+    /// Orbit around a level entity, for inspecting models
+    Orbit,
+    /// Free-fly debug camera
+    Free,
+}
+impl CameraMode
+{
+    /// Set if the player ship should get the flight controls in this mode
+    pub fn controls_ship(&self) -> bool
+    {
+        match *self
+        {
+        CameraMode::Chase | CameraMode::Cockpit => true,
+        CameraMode::Orbit | CameraMode::Free => false,
+        }
+    }
+}
+
+/// Camera placement, as looking from `eye` at `target`
+#[derive(Copy,Clone,Debug)]
+struct CameraView
+{
+    eye: [f32; 3],
+    target: [f32; 3],
+    up: [f32; 3],
+}
+impl CameraView
+{
+    fn lerp(&self, other: &CameraView, t: f32) -> CameraView
+    {
+        let l = |a: [f32; 3], b: [f32; 3]| [ a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t, a[2] + (b[2] - a[2]) * t ];
+        CameraView {
+            eye: l(self.eye, other.eye),
+            target: l(self.target, other.target),
+            up: l(self.up, other.up),
+        }
+    }
+    /// Camera transform (the inverse of the view matrix)
+    fn matrix(&self) -> Option<Matrix4<f32>>
+    {
+        Matrix4::look_at(Point3::from(self.eye), Point3::from(self.target), Vector3::from(self.up)).invert()
+    }
+}
+
+/// Free-fly debug camera state
+struct FreeCamera
+{
+    x: f32,
+    y: f32,
+    z: f32,
+    tilt_deg: f32,
+    angle_deg: f32,
+}
+impl FreeCamera
+{
+    fn new() -> FreeCamera
+    {
+        FreeCamera {
+            x: 0.,
+            y: 2.0,
+            z: 16.,
+            tilt_deg: 0.,
+            angle_deg: 0.,
+            }
+    }
+
+    fn get_matrix(&self) -> Matrix4<f32>
+    {
+        Matrix4::from_scale(1.)
+            * Matrix4::from_translation([self.x, self.y, self.z].into())
+            * Matrix4::from_angle_y(Deg(self.angle_deg))
+            * Matrix4::from_angle_x(Deg(self.tilt_deg))
+    }
+    fn view(&self) -> CameraView
+    {
+        let m = self.get_matrix();
+        CameraView {
+            eye: [self.x, self.y, self.z],
+            target: [self.x - m.z.x, self.y - m.z.y, self.z - m.z.z],
+            up: [m.y.x, m.y.y, m.y.z],
+        }
+    }
+
+    fn shift(&mut self, angle: f32, step: f32)
+    {
+        self.x += angle.to_radians().sin() * step;
+        self.z += angle.to_radians().cos() * step;
+    }
+
+    fn update(&mut self, input: &::amethyst::input::InputHandler<String,String>, dt: f32)
+    {
+        /// Movement speeds (units per second)
+        const SPEED: f32 = 1.5;
+        const VSPEED: f32 = 0.6;
+        /// Turn rate (degrees per second)
+        const TURN_RATE: f32 = 60.;
+
+        self.angle_deg += TURN_RATE * dt * controls::axis(input, controls::AXIS_YAW);
+        if self.angle_deg >= 180. {
+            self.angle_deg -= 360.;
+        }
+        if self.angle_deg <= -180. {
+            self.angle_deg += 360.;
+        }
+        self.tilt_deg = (self.tilt_deg + TURN_RATE * dt * controls::axis(input, controls::AXIS_PITCH)).max(-90.).min(90.);
+
+        let a = self.angle_deg;
+        self.shift(a, -SPEED * dt * controls::axis(input, controls::AXIS_THROTTLE));
+        self.shift(a + 90., SPEED * dt * controls::axis(input, controls::AXIS_STRAFE));
+        self.y += VSPEED * dt * controls::axis(input, controls::AXIS_LIFT);
+    }
+}
+
+/// Input actions that select each mode
+const MODE_ACTIONS: [(&str, CameraMode); 4] = [
+    (controls::ACTION_CAMERA_CHASE, CameraMode::Chase),
+    (controls::ACTION_CAMERA_COCKPIT, CameraMode::Cockpit),
+    (controls::ACTION_CAMERA_ORBIT, CameraMode::Orbit),
+    (controls::ACTION_CAMERA_FREE, CameraMode::Free),
+    ];
+
+/// Positions the camera according to the active `CameraMode`, blending between modes when it changes
+pub struct CameraSystem
+{
+    free: FreeCamera,
+    orbit_target: Option<ecs::Entity>,
+    orbit_angle: f32,
+    /// View when the last mode change happened, and the time since then
+    transition: Option<(CameraView, f32)>,
+    last_view: Option<CameraView>,
+    presses: controls::PressTracker,
+}
+impl CameraSystem
+{
+    pub fn new() -> CameraSystem
+    {
+        CameraSystem {
+            free: FreeCamera::new(),
+            orbit_target: None,
+            orbit_angle: 0.,
+            transition: None,
+            last_view: None,
+            presses: controls::PressTracker::default(),
+        }
+    }
+}
+impl<'s> ecs::System<'s> for CameraSystem
+{
+    type SystemData = (
+        ecs::Entities<'s>,
+        ecs::FetchMut<'s, CameraMode>,
+        ecs::Fetch<'s, ::amethyst::core::timing::Time>,
+        ecs::Fetch<'s, ::amethyst::input::InputHandler<String,String>>,
+        ecs::ReadStorage<'s, PlayerShip>,
+        ecs::ReadStorage<'s, EntityType>,
+        ecs::ReadStorage<'s, a_renderer::Camera>,
+        ecs::WriteStorage<'s, Transform>,
+        );
+    fn run(&mut self, (entities, mut mode, time, input, ships, entity_types, cam, mut transforms): Self::SystemData)
+    {
+        let dt = time.delta_seconds();
+
+        // Mode switching
+        for &(action, m) in &MODE_ACTIONS
+        {
+            if self.presses.pressed(&input, action) && *mode != m
+            {
+                debug!("Camera mode {:?}", m);
+                *mode = m;
+                if let Some(v) = self.last_view {
+                    self.transition = Some( (v, 0.) );
+                }
+            }
+        }
+        if self.presses.pressed(&input, controls::ACTION_CAMERA_ORBIT_NEXT) || (*mode == CameraMode::Orbit && self.orbit_target.map(|e| !entities.is_alive(e)).unwrap_or(true))
+        {
+            // Select the entity following the current one (wrapping around)
+            let cur = self.orbit_target.map(|e| e.id());
+            let mut candidates: Vec<_> = (&*entities, &entity_types).join().map(|(e, _)| e).collect();
+            candidates.sort_by_key(|e| e.id());
+            self.orbit_target = candidates.iter().cloned()
+                .find(|e| cur.map(|c| e.id() > c).unwrap_or(true))
+                .or(candidates.first().cloned());
+        }
+
+        let ship = ships.join().next().map(|s| s.render_state);
+        let orbit_target = self.orbit_target.and_then(|e| transforms.get(e).map(|t| [t.0.w.x, t.0.w.y, t.0.w.z]));
+        let view = match *mode
+            {
+            CameraMode::Chase => ship.map(|ship| {
+                let fwd = ship.forward();
+                let target = ship.position;
+                CameraView {
+                    eye: [
+                        target[0] - fwd[0] * CHASE_DISTANCE,
+                        target[1] - fwd[1] * CHASE_DISTANCE + CHASE_HEIGHT,
+                        target[2] - fwd[2] * CHASE_DISTANCE,
+                        ],
+                    target: target,
+                    up: [0., 1., 0.],
+                }
+                }),
+            CameraMode::Cockpit => ship.map(|ship| {
+                let m = ship.matrix();
+                let fwd = ship.forward();
+                let eye = [ ship.position[0] + m.y.x * COCKPIT_HEIGHT, ship.position[1] + m.y.y * COCKPIT_HEIGHT, ship.position[2] + m.y.z * COCKPIT_HEIGHT ];
+                CameraView {
+                    eye: eye,
+                    target: [ eye[0] + fwd[0], eye[1] + fwd[1], eye[2] + fwd[2] ],
+                    up: [m.y.x, m.y.y, m.y.z],
+                }
+                }),
+            CameraMode::Orbit => orbit_target.map(|target| {
+                self.orbit_angle = (self.orbit_angle + ORBIT_RATE * dt) % 360.;
+                let a = self.orbit_angle.to_radians();
+                CameraView {
+                    eye: [ target[0] + a.sin() * ORBIT_DISTANCE, target[1] + ORBIT_HEIGHT, target[2] + a.cos() * ORBIT_DISTANCE ],
+                    target: target,
+                    up: [0., 1., 0.],
+                }
+                }),
+            CameraMode::Free => {
+                self.free.update(&input, dt);
+                Some(self.free.view())
+                },
+            };
+        let view = match view
+            {
+            Some(v) => v,
+            None => self.free.view(),
+            };
+
+        // Blend from the view at the time of the last mode change
+        let out = match self.transition
+            {
+            Some((from, t)) => {
+                let t = t + dt / TRANSITION_TIME;
+                if t >= 1. {
+                    self.transition = None;
+                    view
+                }
+                else {
+                    self.transition = Some( (from, t) );
+                    // Smoothstep, to ease in and out
+                    from.lerp(&view, t * t * (3. - 2. * t))
+                }
+                },
+            None => view,
+            };
+        self.last_view = Some(out);
+
+        if let Some(m) = out.matrix()
+        {
+            for (_c, transform) in (&cam, &mut transforms).join()
+            {
+                transform.0 = m;
+            }
+        }
+    }
+}
+
+/// Add the camera mode resource, and a camera entity
+pub fn initialise(world: &mut ::amethyst::prelude::World)
+{
+    world.add_resource(CameraMode::Chase);
+    let transform = FreeCamera::new().get_matrix();
+    world
+        .create_entity()
+        .with(a_renderer::Camera::from(a_renderer::Projection::perspective(1.3, Deg(60.0))))
+        .with(Transform(transform.into()))
+        .build();
+}
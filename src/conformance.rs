@@ -0,0 +1,243 @@
+//!
+//! Retail data conformance check (an ignored test, run with `cargo test -- --ignored`)
+//!
+//! Walks every file in `STARTUP.POD` and `FURY3.POD`, runs it through the matching parser, and reports how many files
+//! of each type parsed, used a `.BIN` block type the parser doesn't know, failed, or panicked. Problems with the
+//! archives' file tables (from `PodArchive::check`) are also listed. Needs `FURY3_SYSTEM` to be set, as the
+//! retail data isn't redistributable.
+use std::collections::{BTreeMap, HashSet};
+use datafile;
+use terrain;
+use super::BoxError;
+
+/// Outcome of parsing one file
+enum Outcome
+{
+    Ok,
+    /// No parser for this file type
+    Skipped,
+    /// Model with a block type that isn't decoded yet (a gap in the parser, not a bad file)
+    UnknownBlock(u32),
+    Failed(String),
+    Panicked(String),
+}
+
+#[derive(Default)]
+struct Counts
+{
+    ok: usize,
+    skipped: usize,
+    unknown_block: usize,
+    failed: usize,
+    panicked: usize,
+}
+
+/// Silences the panic hook (as panics are caught and counted), restoring the previous hook when dropped
+struct QuietPanics
+{
+    prev_hook: Option<Box<Fn(&::std::panic::PanicInfo) + Sync + Send + 'static>>,
+}
+impl QuietPanics
+{
+    fn new() -> QuietPanics
+    {
+        let prev_hook = ::std::panic::take_hook();
+        ::std::panic::set_hook(Box::new(|_| {}));
+        QuietPanics { prev_hook: Some(prev_hook) }
+    }
+}
+impl Drop for QuietPanics
+{
+    fn drop(&mut self)
+    {
+        // The hook can't be changed while unwinding
+        if let (Some(h), false) = (self.prev_hook.take(), ::std::thread::panicking()) {
+            ::std::panic::set_hook(h);
+        }
+    }
+}
+
+#[test]
+#[ignore]
+fn retail_archives()
+{
+    let system_dir = match ::std::env::var_os(super::SYSTEM_DIR_VAR)
+        {
+        Some(v) => ::std::path::PathBuf::from(v),
+        None => panic!("{} must point at the game's data to run the conformance check", super::SYSTEM_DIR_VAR),
+        };
+    let (counts, problems) = check_archives(&system_dir).unwrap();
+
+    for p in &problems
+    {
+        println!("{}", p);
+    }
+    println!("{:<6} {:>6} {:>8} {:>6} {:>8} {:>8}", "Type", "OK", "Unknown", "Failed", "Panicked", "Skipped");
+    let mut total = Counts::default();
+    for (ext, c) in &counts
+    {
+        println!("{:<6} {:>6} {:>8} {:>6} {:>8} {:>8}", ext, c.ok, c.unknown_block, c.failed, c.panicked, c.skipped);
+        total.ok += c.ok;
+        total.unknown_block += c.unknown_block;
+        total.failed += c.failed;
+        total.panicked += c.panicked;
+        total.skipped += c.skipped;
+    }
+    println!("{:<6} {:>6} {:>8} {:>6} {:>8} {:>8}", "Total", total.ok, total.unknown_block, total.failed, total.panicked, total.skipped);
+    // Unknown blocks are a known gap in the model parser, anything else is a bug
+    assert!(total.failed == 0 && total.panicked == 0, "{} files failed and {} panicked", total.failed, total.panicked);
+}
+
+/// The synthetic archives pass, with only the level's heightmap checked out of the `.RAW` files
+#[test]
+fn synthetic_archives()
+{
+    let dir = ::std::env::temp_dir().join(format!("fury3clone-conformance-{}", ::std::process::id()));
+    ::std::fs::create_dir_all(&dir).unwrap();
+    let (startup, game) = datafile::synth::level_archives("EGYPT", &[]);
+    startup.write(::std::fs::File::create(dir.join("STARTUP.POD")).unwrap()).unwrap();
+    game.write(::std::fs::File::create(dir.join("FURY3.POD")).unwrap()).unwrap();
+    let (counts, problems) = check_archives(&dir).unwrap();
+    ::std::fs::remove_dir_all(&dir).unwrap();
+
+    assert!(problems.is_empty(), "{:?}", problems);
+    let raw = &counts["RAW"];
+    assert_eq!((raw.ok, raw.skipped), (1, datafile::synth::TEXTURE_COUNT + 1));
+    for (ext, c) in &counts {
+        assert_eq!(c.failed + c.panicked + c.unknown_block, 0, "{}", ext);
+    }
+}
+
+/// Parse every file in the archives, returning the counts for each file type and a description of each problem
+fn check_archives(system_dir: &::std::path::Path) -> Result<(BTreeMap<String, Counts>, Vec<String>), BoxError>
+{
+    // Panics are caught and counted, so don't let the default hook print each one
+    let _quiet = QuietPanics::new();
+
+    let mut pods = Vec::new();
+    for &pod_name in &["STARTUP.POD", "FURY3.POD"]
+    {
+        pods.push( (pod_name, datafile::PodArchive::from_file_mapped(system_dir.join(pod_name))?) );
+    }
+    let heightmaps = level_heightmaps(&pods);
+
+    let mut counts: BTreeMap<String, Counts> = BTreeMap::new();
+    let mut problems = Vec::new();
+    for &(pod_name, ref pod) in &pods
+    {
+        let report = pod.check();
+        println!("{}: {} files, {} integrity problems", pod_name, report.file_count, report.problems.len());
+        for p in &report.problems
+        {
+            println!("{}: {}", pod_name, p);
+        }
+        for name in pod.file_names()
+        {
+            let ext = match name.rfind('.')
+                {
+                Some(p) => name[p+1 ..].to_owned(),
+                None => String::new(),
+                };
+            // Textures are also `.RAW`, and aren't always square
+            let outcome = if ext == "RAW" && !heightmaps.contains(&name.to_ascii_uppercase()) {
+                Outcome::Skipped
+            }
+            else {
+                let fh = pod.open_file(&name)?;
+                let ext = &ext;
+                match ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(move || check_file(ext, fh)))
+                {
+                Ok(v) => v,
+                Err(e) => Outcome::Panicked(
+                    e.downcast_ref::<&str>().map(|v| v.to_string())
+                        .or_else(|| e.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| "(unknown)".to_owned())
+                    ),
+                }
+                };
+
+            let c = counts.entry(ext).or_insert_with(Counts::default);
+            match outcome
+            {
+            Outcome::Ok => c.ok += 1,
+            Outcome::Skipped => c.skipped += 1,
+            Outcome::UnknownBlock(id) => {
+                c.unknown_block += 1;
+                problems.push(format!("{} {}: unknown block 0x{:02x}", pod_name, name, id));
+                },
+            Outcome::Failed(msg) => {
+                c.failed += 1;
+                problems.push(format!("{} {}: error: {}", pod_name, name, msg));
+                },
+            Outcome::Panicked(msg) => {
+                c.panicked += 1;
+                problems.push(format!("{} {}: panic: {}", pod_name, name, msg));
+                },
+            }
+        }
+    }
+    Ok( (counts, problems) )
+}
+
+/// Full names of the heightmaps used by the levels in the archives (levels that don't parse are reported as failures
+/// in the main pass)
+fn level_heightmaps(pods: &[(&str, datafile::PodArchive)]) -> HashSet<String>
+{
+    let mut rv = HashSet::new();
+    for &(_, ref pod) in pods
+    {
+        for name in pod.file_names()
+        {
+            if !name.ends_with(".LVL") {
+                continue;
+            }
+            let level = match pod.open_file(&name).ok().and_then(|fh| datafile::Level::from_file(fh).ok())
+                {
+                Some(v) => v,
+                None => continue,
+                };
+            rv.insert( format!(r"DATA\{}", level.heightmap.to_ascii_uppercase()) );
+        }
+    }
+    rv
+}
+
+fn check_file(ext: &str, mut fh: datafile::FileHandle) -> Outcome
+{
+    use std::io::Read;
+    let rv: Result<(), BoxError> = match ext
+        {
+        "BIN" => datafile::Model::from_bin_file(fh).map(|_| ()).map_err(|e| e.into()),
+        "LVL" => datafile::Level::from_file(fh).map(|_| ()).map_err(|e| e.into()),
+        "DEF" => datafile::EntityFile::from_file(fh).map(|_| ()).map_err(|e| e.into()),
+        // Heightmaps and texture maps are square
+        "RAW" | "CLR" => terrain::ByteGrid::from_reader(fh).map(|_| ()).map_err(|e| e.into()),
+        "ACT" => if fh.size() == 256*3 { Ok( () ) } else { Err(format!("Palette is {} bytes, not 768", fh.size()).into()) },
+        "TEX" => {
+            let mut data = String::new();
+            match fh.read_to_string(&mut data)
+            {
+            Err(e) => Err(e.into()),
+            Ok(_) => {
+                let mut it = data.split("\r\n").filter(|v| v.len() > 0);
+                match it.next().map(|v| v.trim().parse::<usize>())
+                {
+                Some(Ok(n)) if n == it.count() => Ok( () ),
+                Some(Ok(n)) => Err(format!("Texture count {} doesn't match the list", n).into()),
+                _ => Err("Bad texture count".into()),
+                }
+                },
+            }
+            },
+        _ => return Outcome::Skipped,
+        };
+    match rv
+    {
+    Ok(()) => Outcome::Ok,
+    Err(e) => match e.downcast_ref::<::std::io::Error>().and_then(|e| e.get_ref()).and_then(|e| e.downcast_ref::<datafile::UnknownBlock>())
+        {
+        Some(b) => Outcome::UnknownBlock(b.id),
+        None => Outcome::Failed(e.to_string()),
+        },
+    }
+}
@@ -0,0 +1,68 @@
+//!
+//! Names of the input actions and axes (bound in `resources/input.ron`)
+//!
+use amethyst::input::InputHandler;
+
+/// Throttle up (positive) or down
+pub const AXIS_THROTTLE: &str = "throttle";
+/// Nose down (positive) or up, like a flight stick
+pub const AXIS_PITCH: &str = "pitch";
+/// Turn left (positive) or right
+pub const AXIS_YAW: &str = "yaw";
+/// Roll left (positive) or right
+pub const AXIS_ROLL: &str = "roll";
+/// Free camera sideways movement, right is positive
+pub const AXIS_STRAFE: &str = "strafe";
+/// Free camera vertical movement, up is positive
+pub const AXIS_LIFT: &str = "lift";
+
+// NOTE: Bound, but there are no weapons yet
+#[allow(dead_code)]
+pub const ACTION_FIRE: &str = "fire";
+#[allow(dead_code)]
+pub const ACTION_SWITCH_WEAPON: &str = "switch_weapon";
+pub const ACTION_PAUSE: &str = "pause";
+pub const ACTION_QUIT: &str = "quit";
+pub const ACTION_CAMERA_CHASE: &str = "camera_chase";
+pub const ACTION_CAMERA_COCKPIT: &str = "camera_cockpit";
+pub const ACTION_CAMERA_ORBIT: &str = "camera_orbit";
+pub const ACTION_CAMERA_FREE: &str = "camera_free";
+/// Move the orbit camera to the next level entity
+pub const ACTION_CAMERA_ORBIT_NEXT: &str = "camera_orbit_next";
+
+/// Current value of an axis, zero if it isn't bound
+pub fn axis(input: &InputHandler<String,String>, name: &str) -> f32
+{
+    input.axis_value(name).unwrap_or(0.) as f32
+}
+
+/// Detects action presses (the frame an action goes down), for actions that shouldn't repeat while held
+#[derive(Default)]
+pub struct PressTracker
+{
+    /// Actions held down last time they were checked
+    held: Vec<&'static str>,
+}
+impl PressTracker
+{
+    /// Returns true if the action has been pressed since the last check
+    pub fn pressed(&mut self, input: &InputHandler<String,String>, action: &'static str) -> bool
+    {
+        let down = input.action_is_down(action).unwrap_or(false);
+        let was_down = self.held.contains(&action);
+        if down && !was_down {
+            self.held.push(action);
+        }
+        else if !down && was_down {
+            self.held.retain(|&v| v != action);
+        }
+        down && !was_down
+    }
+}
+
+/// Set while the game is paused (toggled by `ACTION_PAUSE`)
+///
+/// The flight, collision, destruction, drop and pickup systems skip their update. The cameras keep running, so the
+/// paused scene can still be looked around.
+#[derive(Default)]
+pub struct Paused(pub bool);
@@ -0,0 +1,845 @@
+//!
+//! `.DEF` level entity file (see the "Level Entities" section of `docs/datafile_notes/levels.md`)
+//!
+//! All columns are kept, including the ones with an unknown meaning, so the file can be reproduced exactly. The file is
+//! handled as bytes (text is decoded as Latin-1, so any byte value survives), and the original text of each line is
+//! kept so `write` can reproduce padding, number formatting, line endings and anything after the placements. Lines with
+//! a fixed layout accept extra fields after the known ones, which are kept as text.
+use super::fixed::{Fixed, Frac20};
+
+/// Parsed `.DEF` file
+pub struct EntityFile
+{
+    pub types: Vec<EntityType>,
+    pub placements: Vec<EntityPlacement>,
+    /// Anything after the last placement
+    pub trailing: Vec<u8>,
+    /// Lines as they were read, in file order (empty for a file built in memory)
+    source: Vec<SourceLine>,
+}
+
+/// Entity type definition (14 lines in the file)
+pub struct EntityType
+{
+    // Line 1
+    pub class: u8,
+    pub unk_line1: [i64; 5],
+    /// Model when active
+    pub model: String,
+    /// Model when destroyed
+    pub model_destroyed: String,
+    /// Any fields after the destroyed model
+    pub extra_line1: Vec<String>,
+    // Line 2 (five values in the stock files, the first is the hit points)
+    pub unk_line2: Vec<i64>,
+    // Line 3
+    /// Drops, as (chance percentage, item ID), see `drop`
+    pub drops: [(u8, i8); 2],
+    /// Any fields after the drops
+    pub extra_line3: Vec<String>,
+    // Line 4
+    pub unk_line4: Vec<i64>,
+    // Line 5 is `;NewHit`
+    pub new_hit: Vec<i64>,
+    // Line 7 is `!NewAtakRet`
+    pub new_atak_ret: Vec<i64>,
+    /// Description for mission briefing (the text lines are decoded as Latin-1)
+    pub description: String,
+    // Line 10 is `#New2ndweapon`
+    pub new_2nd_weapon: Vec<i64>,
+    // Line 12 is `%SFX`
+    pub sfx: [String; 2],
+}
+
+impl EntityType
+{
+    /// Drop table entry `idx` as (chance percentage, item ID), `None` if the slot is empty
+    ///
+    /// Empty slots have a zero chance or a negative item ID (-1), the raw values are kept in `drops` for `write`.
+    pub fn drop(&self, idx: usize) -> Option<(u8, u8)>
+    {
+        let (chance, item) = self.drops[idx];
+        if chance == 0 || item < 0 {
+            None
+        }
+        else {
+            Some( (chance, item as u8) )
+        }
+    }
+}
+
+/// Entity placement (a single line in the file)
+pub struct EntityPlacement
+{
+    /// Index into `EntityFile::types`
+    pub ty: usize,
+    pub flags: u16,
+    // Position (12.20 is a guess)
+    pub x: Fixed<Frac20>,
+    pub y: Fixed<Frac20>,
+    pub z: Fixed<Frac20>,
+    pub unk1: u32,
+    pub unk2: u32,
+    pub unk3: u32,
+    /// Any fields after `unk3`
+    pub extra: Vec<String>,
+}
+
+/// Location and description of a malformed entry, wrapped in an `InvalidData` IO error
+#[derive(Debug)]
+pub struct ParseError
+{
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based column (byte) of the offending field
+    pub column: usize,
+    pub message: String,
+}
+impl ::std::fmt::Display for ParseError
+{
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result
+    {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+impl ::std::error::Error for ParseError
+{
+    fn description(&self) -> &str {
+        "Malformed .DEF file"
+    }
+}
+
+fn error(line: usize, column: usize, message: String) -> ::std::io::Error
+{
+    ::std::io::Error::new(::std::io::ErrorKind::InvalidData, ParseError { line: line, column: column, message: message })
+}
+
+impl EntityFile
+{
+    /// A file with no source formatting, which `write` outputs with CRLF line endings (used for the synthetic data)
+    #[cfg(test)]
+    pub fn new(types: Vec<EntityType>, placements: Vec<EntityPlacement>) -> EntityFile
+    {
+        EntityFile {
+            types: types,
+            placements: placements,
+            trailing: Vec::new(),
+            source: Vec::new(),
+        }
+    }
+
+    pub fn from_file<F: ::std::io::Read>(mut file: F) -> ::std::io::Result<EntityFile>
+    {
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        let mut lines = Lines { data: &data, pos: 0, line: 0, source: Vec::new() };
+
+        let ty_count: usize = lines.next("type count")?.fields().single("type count")?;
+        // NOTE: Counts aren't trusted for pre-allocation, a corrupt file could ask for any size
+        let mut types = Vec::new();
+        for _ in 0 .. ty_count
+        {
+            let mut l1 = lines.next("model line")?.fields();
+            let class = l1.next("class")?;
+            let unk_line1 = [ l1.next("unknown")?, l1.next("unknown")?, l1.next("unknown")?, l1.next("unknown")?, l1.next("unknown")? ];
+            let model = l1.next_str("model")?;
+            let model_destroyed = l1.next_str("destroyed model")?;
+            let extra_line1 = l1.extra()?;
+
+            let unk_line2 = lines.next("line 2")?.fields().rest()?;
+
+            let mut l3 = lines.next("drop line")?.fields();
+            let drops = [
+                (l3.next("drop chance 1")?, l3.next("drop item 1")?),
+                (l3.next("drop chance 2")?, l3.next("drop item 2")?),
+                ];
+            let extra_line3 = l3.extra()?;
+
+            let unk_line4 = lines.next("line 4")?.fields().rest()?;
+            lines.next("marker")?.marker(";NewHit")?;
+            let new_hit = lines.next("hit line")?.fields().rest()?;
+            lines.next("marker")?.marker("!NewAtakRet")?;
+            let new_atak_ret = lines.next("attack line")?.fields().rest()?;
+            let description = lines.next("description")?.text();
+            lines.next("marker")?.marker("#New2ndweapon")?;
+            let new_2nd_weapon = lines.next("secondary weapon line")?.fields().rest()?;
+            lines.next("marker")?.marker("%SFX")?;
+            let sfx = [
+                lines.next("sound effect")?.text(),
+                lines.next("sound effect")?.text(),
+                ];
+
+            types.push(EntityType {
+                class: class,
+                unk_line1: unk_line1,
+                model: model,
+                model_destroyed: model_destroyed,
+                extra_line1: extra_line1,
+                unk_line2: unk_line2,
+                drops: drops,
+                extra_line3: extra_line3,
+                unk_line4: unk_line4,
+                new_hit: new_hit,
+                new_atak_ret: new_atak_ret,
+                description: description,
+                new_2nd_weapon: new_2nd_weapon,
+                sfx: sfx,
+                });
+        }
+
+        let ent_count: usize = lines.next("placement count")?.fields().single("placement count")?;
+        let mut placements = Vec::new();
+        for _ in 0 .. ent_count
+        {
+            let mut l = lines.next("placement")?.fields();
+            let ty_col = l.column();
+            let p = EntityPlacement {
+                ty: l.next("type")?,
+                flags: l.next("flags")?,
+                x: Fixed::from_bits(l.next("x")?),
+                y: Fixed::from_bits(l.next("y")?),
+                z: Fixed::from_bits(l.next("z")?),
+                unk1: l.next("unk1")?,
+                unk2: l.next("unk2")?,
+                unk3: l.next("unk3")?,
+                extra: l.extra()?,
+                };
+            if p.ty >= types.len() {
+                return Err(error(l.line, ty_col, format!("Entity type {} out of range (only {} types)", p.ty, types.len())));
+            }
+            placements.push(p);
+        }
+
+        Ok(EntityFile {
+            types: types,
+            placements: placements,
+            trailing: data[lines.pos..].to_vec(),
+            source: lines.source,
+            })
+    }
+
+    /// Write out in the original format, such that `from_file` then `write` reproduces the input byte for byte
+    ///
+    /// Fields with the same value as the source line at the same position are written with their original text (and
+    /// lines with their original ending), anything else is written in the game's own format: no padding, and CRLF
+    /// line endings.
+    pub fn write<W: ::std::io::Write>(&self, mut out: W) -> ::std::io::Result<()>
+    {
+        let lines = self.output_lines();
+        for (i, l) in lines.iter().enumerate()
+        {
+            let src = self.source.get(i);
+            match *l
+            {
+            OutLine::Text(ref text) => {
+                let text = latin1_encode(text)?;
+                match src
+                {
+                Some(s) if trim_end(&s.text) == trim_end(&text) => out.write_all(&s.text)?,
+                _ => out.write_all(&text)?,
+                }
+                },
+            OutLine::Fields(ref fields) => {
+                let src_fields: Vec<&[u8]> = match src
+                    {
+                    Some(s) if trim(&s.text).is_empty() => Vec::new(),
+                    Some(s) => s.text.split(|&b| b == b',').collect(),
+                    None => Vec::new(),
+                    };
+                if fields.is_empty() {
+                    // Keep any whitespace on an empty line
+                    if let Some(s) = src {
+                        if trim(&s.text).is_empty() {
+                            out.write_all(&s.text)?;
+                        }
+                    }
+                }
+                for (j, f) in fields.iter().enumerate()
+                {
+                    if j > 0 {
+                        out.write_all(b",")?;
+                    }
+                    let canonical = match *f
+                        {
+                        Field::Num(v) => v.to_string().into_bytes(),
+                        Field::Str(ref v) => latin1_encode(v)?,
+                        };
+                    match src_fields.get(j)
+                    {
+                    Some(raw) if src_fields.len() == fields.len() && f.matches(raw) => out.write_all(raw)?,
+                    _ => out.write_all(&canonical)?,
+                    }
+                }
+                },
+            }
+            // A source line without an ending was the last line, only keep that if it's still last
+            match src
+            {
+            Some(s) if !s.ending.is_empty() || i + 1 == lines.len() => out.write_all(&s.ending)?,
+            _ => out.write_all(b"\r\n")?,
+            }
+        }
+        out.write_all(&self.trailing)
+    }
+
+    /// Values of every line, in file order
+    fn output_lines(&self) -> Vec<OutLine>
+    {
+        fn nums<T: Copy + Into<i64>>(v: &[T]) -> OutLine {
+            OutLine::Fields( v.iter().map(|&v| Field::Num(v.into())).collect() )
+        }
+        let text = |s: &str| OutLine::Text(s.to_owned());
+
+        let mut rv = Vec::new();
+        rv.push( nums(&[self.types.len() as i64]) );
+        for t in &self.types
+        {
+            let mut l1 = vec![ Field::Num(t.class as i64) ];
+            l1.extend( t.unk_line1.iter().map(|&v| Field::Num(v)) );
+            l1.push( Field::Str(t.model.clone()) );
+            l1.push( Field::Str(t.model_destroyed.clone()) );
+            l1.extend( t.extra_line1.iter().map(|v| Field::Str(v.clone())) );
+            rv.push( OutLine::Fields(l1) );
+            rv.push( nums(&t.unk_line2) );
+            let mut l3: Vec<_> = [ t.drops[0].0 as i64, t.drops[0].1 as i64, t.drops[1].0 as i64, t.drops[1].1 as i64 ]
+                .iter().map(|&v| Field::Num(v)).collect();
+            l3.extend( t.extra_line3.iter().map(|v| Field::Str(v.clone())) );
+            rv.push( OutLine::Fields(l3) );
+            rv.push( nums(&t.unk_line4) );
+            rv.push( text(";NewHit") );
+            rv.push( nums(&t.new_hit) );
+            rv.push( text("!NewAtakRet") );
+            rv.push( nums(&t.new_atak_ret) );
+            rv.push( text(&t.description) );
+            rv.push( text("#New2ndweapon") );
+            rv.push( nums(&t.new_2nd_weapon) );
+            rv.push( text("%SFX") );
+            rv.push( text(&t.sfx[0]) );
+            rv.push( text(&t.sfx[1]) );
+        }
+
+        rv.push( nums(&[self.placements.len() as i64]) );
+        for p in &self.placements
+        {
+            let mut l: Vec<_> = [
+                p.ty as i64, p.flags as i64, p.x.to_bits() as i64, p.y.to_bits() as i64, p.z.to_bits() as i64,
+                p.unk1 as i64, p.unk2 as i64, p.unk3 as i64,
+                ].iter().map(|&v| Field::Num(v)).collect();
+            l.extend( p.extra.iter().map(|v| Field::Str(v.clone())) );
+            rv.push( OutLine::Fields(l) );
+        }
+        rv
+    }
+}
+
+/// A line of the file as it was read
+struct SourceLine
+{
+    /// Line content, without the ending
+    text: Vec<u8>,
+    /// `\r\n`, `\n`, or empty for a last line without one
+    ending: Vec<u8>,
+}
+
+/// A line to be written by `EntityFile::write`
+enum OutLine
+{
+    /// Comma-separated fields
+    Fields(Vec<Field>),
+    /// Whole line of text (which may contain commas)
+    Text(String),
+}
+enum Field
+{
+    Num(i64),
+    Str(String),
+}
+impl Field
+{
+    /// Check if the source text of a field has this value (ignoring padding and number formatting)
+    fn matches(&self, raw: &[u8]) -> bool
+    {
+        match *self
+        {
+        Field::Num(v) => ::std::str::from_utf8(trim(raw)).ok().and_then(|s| s.parse::<i64>().ok()) == Some(v),
+        Field::Str(ref v) => trim_end(raw) == trim_end(&latin1_encode(v).unwrap_or_default()),
+        }
+    }
+}
+
+fn latin1_decode(b: &[u8]) -> String
+{
+    b.iter().map(|&b| b as char).collect()
+}
+fn latin1_encode(s: &str) -> ::std::io::Result<Vec<u8>>
+{
+    s.chars()
+        .map(|c| if (c as u32) < 0x100 {
+                Ok(c as u8)
+            }
+            else {
+                Err(::std::io::Error::new(::std::io::ErrorKind::InvalidInput, format!("{:?} can't be written to a .DEF file", c)))
+            })
+        .collect()
+}
+fn trim_end(b: &[u8]) -> &[u8]
+{
+    let len = b.iter().rposition(|c| !c.is_ascii_whitespace()).map(|p| p + 1).unwrap_or(0);
+    &b[..len]
+}
+fn trim(b: &[u8]) -> &[u8]
+{
+    let b = trim_end(b);
+    let start = b.iter().position(|c| !c.is_ascii_whitespace()).unwrap_or(b.len());
+    &b[start..]
+}
+
+/// Line reader, recording each line for `EntityFile::write`
+struct Lines<'a>
+{
+    data: &'a [u8],
+    /// Byte offset of the next line
+    pos: usize,
+    /// Number of lines read
+    line: usize,
+    source: Vec<SourceLine>,
+}
+impl<'a> Lines<'a>
+{
+    fn next(&mut self, what: &str) -> ::std::io::Result<Line<'a>>
+    {
+        if self.pos >= self.data.len() {
+            return Err(error(self.line + 1, 1, format!("Unexpected end of file, expected {}", what)));
+        }
+        let data = self.data;
+        let rest = &data[self.pos..];
+        let (text, ending) = match rest.iter().position(|&b| b == b'\n')
+            {
+            Some(i) if i > 0 && rest[i-1] == b'\r' => (&rest[..i-1], &rest[i-1 .. i+1]),
+            Some(i) => (&rest[..i], &rest[i .. i+1]),
+            None => (rest, &rest[rest.len()..]),
+            };
+        self.pos += text.len() + ending.len();
+        self.line += 1;
+        self.source.push(SourceLine { text: text.to_vec(), ending: ending.to_vec() });
+        Ok(Line { line: self.line, text: trim_end(text) })
+    }
+}
+
+struct Line<'a>
+{
+    line: usize,
+    /// Line content, without trailing whitespace
+    text: &'a [u8],
+}
+impl<'a> Line<'a>
+{
+    fn fields(&self) -> Fields<'a>
+    {
+        Fields { line: self.line, text: self.text, pos: Some(0) }
+    }
+    fn text(&self) -> String
+    {
+        latin1_decode(self.text)
+    }
+    fn marker(&self, expected: &str) -> ::std::io::Result<()>
+    {
+        if self.text != expected.as_bytes() {
+            return Err(error(self.line, 1, format!("Expected {:?}, got {:?}", expected, self.text())));
+        }
+        Ok( () )
+    }
+}
+
+/// Comma-separated fields of a line, tracking the column of each
+struct Fields<'a>
+{
+    line: usize,
+    text: &'a [u8],
+    /// Byte offset of the next field, `None` once all fields have been consumed
+    pos: Option<usize>,
+}
+impl<'a> Fields<'a>
+{
+    /// Column of the next field
+    fn column(&self) -> usize
+    {
+        self.pos.unwrap_or(self.text.len()) + 1
+    }
+
+    fn next_bytes(&mut self, what: &str) -> ::std::io::Result<&'a [u8]>
+    {
+        let pos = match self.pos
+            {
+            Some(v) => v,
+            None => return Err(error(self.line, self.column(), format!("Missing field: {}", what))),
+            };
+        let text = self.text;
+        let rest = &text[pos..];
+        Ok(match rest.iter().position(|&b| b == b',')
+        {
+        Some(i) => {
+            self.pos = Some(pos + i + 1);
+            &rest[..i]
+            },
+        None => {
+            self.pos = None;
+            rest
+            },
+        })
+    }
+
+    fn next_str(&mut self, what: &str) -> ::std::io::Result<String>
+    {
+        Ok( latin1_decode(self.next_bytes(what)?) )
+    }
+
+    fn next<T>(&mut self, what: &str) -> ::std::io::Result<T>
+    where
+        T: ::std::str::FromStr,
+        T::Err: ::std::fmt::Display,
+    {
+        let col = self.column();
+        let s = self.next_bytes(what)?;
+        let bad = |msg: String| error(self.line, col, format!("Bad {} {:?}: {}", what, latin1_decode(s), msg));
+        match ::std::str::from_utf8(trim(s))
+        {
+        Ok(v) => v.parse().map_err(|e: T::Err| bad(e.to_string())),
+        Err(_) => Err(bad("not a number".to_owned())),
+        }
+    }
+
+    /// The only field on the line
+    fn single<T>(&mut self, what: &str) -> ::std::io::Result<T>
+    where
+        T: ::std::str::FromStr,
+        T::Err: ::std::fmt::Display,
+    {
+        let rv = self.next(what)?;
+        self.end()?;
+        Ok(rv)
+    }
+
+    /// All remaining fields (none if the line is empty)
+    fn rest<T>(&mut self) -> ::std::io::Result<Vec<T>>
+    where
+        T: ::std::str::FromStr,
+        T::Err: ::std::fmt::Display,
+    {
+        let mut rv = Vec::new();
+        if trim(self.text).is_empty() {
+            self.pos = None;
+        }
+        while self.pos.is_some() {
+            rv.push( self.next("value")? );
+        }
+        Ok(rv)
+    }
+
+    /// All remaining fields as text (for unknown fields after a fixed layout)
+    fn extra(&mut self) -> ::std::io::Result<Vec<String>>
+    {
+        let mut rv = Vec::new();
+        while self.pos.is_some() {
+            rv.push( self.next_str("field")? );
+        }
+        Ok(rv)
+    }
+
+    /// Check that all fields have been consumed
+    fn end(&self) -> ::std::io::Result<()>
+    {
+        if let Some(pos) = self.pos {
+            return Err(error(self.line, self.column(), format!("Unexpected extra field {:?}", latin1_decode(&self.text[pos..]))));
+        }
+        Ok( () )
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// One type (lines 2-15) and two placements (lines 17-18)
+    const SAMPLE: &str = "1\r\n\
+        2,0,100,0,0,0,TENT2.BIN,TENT2D.BIN\r\n\
+        500,0,0,0,1\r\n\
+        25,3,50,-1\r\n\
+        0,0,0\r\n\
+        ;NewHit\r\n\
+        0,0,0,0\r\n\
+        !NewAtakRet\r\n\
+        \r\n\
+        A TENT, WITH A COMMA\r\n\
+        #New2ndweapon\r\n\
+        0\r\n\
+        %SFX\r\n\
+        NONE\r\n\
+        BOOM.RAW\r\n\
+        2\r\n\
+        0,4096,1048576,0,-2097152,0,0,0\r\n\
+        0,8192,0,0,0,0,0,17\r\n\
+        ";
+
+    fn parse(s: &str) -> ::std::io::Result<EntityFile>
+    {
+        EntityFile::from_file(s.as_bytes())
+    }
+    /// Location of a parse failure
+    fn error_at(s: &str) -> (usize, usize, String)
+    {
+        let e = match parse(s)
+            {
+            Ok(_) => panic!("Parsed successfully: {:?}", s),
+            Err(e) => e,
+            };
+        assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidData);
+        let pe = e.get_ref().and_then(|e| e.downcast_ref::<ParseError>()).expect("Not a ParseError");
+        (pe.line, pe.column, pe.message.clone())
+    }
+    /// The sample with one line replaced
+    fn with_line(line: usize, text: &str) -> String
+    {
+        let mut lines: Vec<_> = SAMPLE.split("\r\n").collect();
+        lines[line - 1] = text;
+        lines.join("\r\n")
+    }
+
+    #[test]
+    fn well_formed()
+    {
+        let f = parse(SAMPLE).unwrap();
+        assert_eq!(f.types.len(), 1);
+        let t = &f.types[0];
+        assert_eq!(t.class, 2);
+        assert_eq!(t.unk_line1, [0, 100, 0, 0, 0]);
+        assert_eq!(t.model, "TENT2.BIN");
+        assert_eq!(t.model_destroyed, "TENT2D.BIN");
+        assert_eq!(t.unk_line2, [500, 0, 0, 0, 1]);
+        assert_eq!(t.drops, [(25, 3), (50, -1)]);
+        assert_eq!(t.unk_line4, vec![0, 0, 0]);
+        assert_eq!(t.new_hit, vec![0, 0, 0, 0]);
+        assert_eq!(t.new_atak_ret, Vec::<i64>::new());
+        assert_eq!(t.description, "A TENT, WITH A COMMA");
+        assert_eq!(t.new_2nd_weapon, vec![0]);
+        assert_eq!(t.sfx, ["NONE".to_owned(), "BOOM.RAW".to_owned()]);
+
+        assert_eq!(f.placements.len(), 2);
+        let p = &f.placements[0];
+        assert_eq!((p.ty, p.flags), (0, 4096));
+        assert_eq!((p.x.to_bits(), p.y.to_bits(), p.z.to_bits()), (1 << 20, 0, -2 << 20));
+        let p = &f.placements[1];
+        assert_eq!((p.flags, p.unk1, p.unk2, p.unk3), (8192, 0, 0, 17));
+    }
+
+    #[test]
+    fn negative_drop_is_empty()
+    {
+        let f = parse(SAMPLE).unwrap();
+        assert_eq!(f.types[0].drop(0), Some( (25, 3) ));
+        assert_eq!(f.types[0].drop(1), None);
+
+        let f = parse(&with_line(4, "0,3,10,7")).unwrap();
+        assert_eq!(f.types[0].drop(0), None);
+        assert_eq!(f.types[0].drop(1), Some( (10, 7) ));
+    }
+
+    #[test]
+    fn truncated()
+    {
+        let lines: Vec<_> = SAMPLE.split("\r\n").collect();
+        // Every prefix of whole lines is missing something (the last "line" is the empty string after the final CRLF)
+        for n in 0 .. lines.len() - 1
+        {
+            let prefix: String = lines[..n].iter().map(|l| format!("{}\r\n", l)).collect();
+            let (line, column, message) = error_at(&prefix);
+            assert_eq!((line, column), (n + 1, 1), "Truncated to {} lines: {}", n, message);
+            assert!(message.starts_with("Unexpected end of file"), "Truncated to {} lines: {}", n, message);
+        }
+        // Cut part way through a placement
+        let (line, _, message) = error_at(&SAMPLE[.. SAMPLE.len() - 7]);
+        assert_eq!(line, 18);
+        assert!(message.starts_with("Missing field"), "{}", message);
+    }
+
+    #[test]
+    fn malformed_types()
+    {
+        // Bad count
+        assert_eq!(error_at(&with_line(1, "one")).0, 1);
+        assert_eq!(error_at(&with_line(1, "1,2")).0, 1);
+        // Class out of range for a u8, in column 1
+        let (line, column, _) = error_at(&with_line(2, "256,0,100,0,0,0,TENT2.BIN,TENT2D.BIN"));
+        assert_eq!((line, column), (2, 1));
+        // Non-numeric value, column of the offending field
+        let (line, column, _) = error_at(&with_line(2, "2,0,1x0,0,0,0,TENT2.BIN,TENT2D.BIN"));
+        assert_eq!((line, column), (2, 5));
+        // Missing destroyed model and drop item
+        assert_eq!(error_at(&with_line(2, "2,0,100,0,0,0,TENT2.BIN")).0, 2);
+        let (line, column, _) = error_at(&with_line(4, "25,3,50"));
+        assert_eq!((line, column), (4, 8));
+        // Drop item out of range for an i8
+        let (line, column, _) = error_at(&with_line(4, "25,3,50,200"));
+        assert_eq!((line, column), (4, 9));
+        // Wrong marker
+        let (line, _, message) = error_at(&with_line(6, ";NewMiss"));
+        assert_eq!(line, 6);
+        assert!(message.contains(";NewHit"), "{}", message);
+    }
+
+    #[test]
+    fn malformed_placements()
+    {
+        // Type index past the end of the type list
+        let (line, column, message) = error_at(&with_line(18, "1,8192,0,0,0,0,0,17"));
+        assert_eq!((line, column), (18, 1));
+        assert!(message.contains("out of range"), "{}", message);
+        // Flags don't fit in 16 bits
+        let (line, column, _) = error_at(&with_line(17, "0,65536,1048576,0,-2097152,0,0,0"));
+        assert_eq!((line, column), (17, 3));
+        // Missing column
+        assert_eq!(error_at(&with_line(17, "0,4096,1048576,0,-2097152,0,0")).0, 17);
+        // Fewer placements than the count says
+        let (line, _, message) = error_at(&with_line(16, "3"));
+        assert_eq!(line, 19);
+        assert!(message.starts_with("Unexpected end of file"), "{}", message);
+    }
+
+    /// Fields past the known layout (and any number of values on line 2) are kept, and written back out
+    #[test]
+    fn trailing_fields()
+    {
+        let extended = with_line(2, "2,0,100,0,0,0,TENT2.BIN,TENT2D.BIN,X, 7")
+            .replace("500,0,0,0,1\r\n", "500,0,0,0,1,2\r\n")
+            .replace("25,3,50,-1\r\n", "25,3,50,-1,,9\r\n")
+            .replace("0,8192,0,0,0,0,0,17\r\n", "0,8192,0,0,0,0,0,17,  1\r\n");
+        let mut f = parse(&extended).unwrap();
+        {
+            let t = &f.types[0];
+            assert_eq!(t.extra_line1, vec!["X".to_owned(), " 7".to_owned()]);
+            assert_eq!(t.unk_line2, vec![500, 0, 0, 0, 1, 2]);
+            assert_eq!(t.drops, [(25, 3), (50, -1)]);
+            assert_eq!(t.extra_line3, vec!["".to_owned(), "9".to_owned()]);
+            assert_eq!(f.placements[0].extra, Vec::<String>::new());
+            assert_eq!(f.placements[1].extra, vec!["  1".to_owned()]);
+        }
+        assert_eq!(round_trip(extended.as_bytes()), extended.as_bytes());
+
+        // Short line 2
+        assert_eq!(parse(&with_line(3, "500")).unwrap().types[0].unk_line2, vec![500]);
+
+        // Extra fields written for a file built in memory
+        f.types[0].extra_line3 = vec!["4".to_owned()];
+        let mut out = Vec::new();
+        f.write(&mut out).unwrap();
+        assert_eq!(parse(::std::str::from_utf8(&out).unwrap()).unwrap().types[0].extra_line3, vec!["4".to_owned()]);
+        assert!(out.windows(13).any(|w| w == b"25,3,50,-1,4\r"));
+    }
+
+    fn round_trip(data: &[u8]) -> Vec<u8>
+    {
+        let f = EntityFile::from_file(data).unwrap();
+        let mut out = Vec::new();
+        f.write(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn round_trip_exact()
+    {
+        assert_eq!(round_trip(SAMPLE.as_bytes()), SAMPLE.as_bytes());
+
+        // Padding, odd number formatting, mixed line endings, trailing whitespace, a non-UTF-8 description, and data
+        // after the placements
+        let odd: &[u8] = b"01 \n\
+            2, 0,+100,0,0,0,TENT2.BIN ,TENT2D.BIN\r\n\
+            500,0,0,0,1\n\
+            25,3,50,-1\r\n\
+            \t\r\n\
+            ;NewHit  \r\n\
+            0,0,0,0\r\n\
+            !NewAtakRet\r\n\
+            \x20\x20\r\n\
+            CAF\xC9 \xA9\r\n\
+            #New2ndweapon\r\n\
+            0\r\n\
+            %SFX\r\n\
+            NONE\r\n\
+            BOOM.RAW\r\n\
+            1\r\n\
+            0,4096,1048576,0,-2097152,0,0,0\r\n\
+            \x1A\x00junk";
+        let f = EntityFile::from_file(odd).unwrap();
+        assert_eq!(f.types[0].description, "CAF\u{C9} \u{A9}");
+        assert_eq!(f.types[0].unk_line1[1], 100);
+        assert_eq!(f.trailing, b"\x1A\x00junk");
+        assert_eq!(round_trip(odd), odd);
+
+        // No line ending on the last line
+        let unterminated = &SAMPLE.as_bytes()[.. SAMPLE.len() - 2];
+        assert_eq!(round_trip(unterminated), unterminated);
+    }
+
+    #[test]
+    fn edited_values()
+    {
+        let odd = SAMPLE.replace("500,0,0,0,1\r\n", "500, 0,0,+0,1\n");
+        let mut f = parse(&odd).unwrap();
+        f.types[0].unk_line2[0] = 7;
+        f.types[0].description = "\u{C9}DITED".to_owned();
+        f.placements.pop();
+        let mut out = Vec::new();
+        f.write(&mut out).unwrap();
+
+        // Unchanged fields and line endings are kept, edited ones are written in the game's format
+        let expected = odd
+            .replace("500, 0,0,+0,1\n", "7, 0,0,+0,1\n")
+            .replace("A TENT, WITH A COMMA", "\u{C9}DITED")
+            .replace("\r\n2\r\n", "\r\n1\r\n")
+            .replace("0,8192,0,0,0,0,0,17\r\n", "");
+        let expected: Vec<u8> = expected.chars().map(|c| c as u8).collect();
+        assert_eq!(out, expected);
+        assert_eq!(EntityFile::from_file(&out[..]).unwrap().types[0].unk_line2[0], 7);
+
+        // Added lines (with no source) get CRLF
+        let mut f = parse(SAMPLE).unwrap();
+        let p = EntityPlacement { extra: Vec::new(), ..f.placements[1] };
+        f.placements.push(p);
+        let mut out = Vec::new();
+        f.write(&mut out).unwrap();
+        assert!(out.ends_with(b"\r\n3\r\n0,4096,1048576,0,-2097152,0,0,0\r\n0,8192,0,0,0,0,0,17\r\n0,8192,0,0,0,0,0,17\r\n"));
+
+        // Text that can't be encoded
+        f.types[0].description = "\u{263A}".to_owned();
+        let e = f.write(Vec::new()).unwrap_err();
+        assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidInput);
+    }
+
+    /// Every `.DEF` in the retail data must be reproduced exactly (needs `FURY3_SYSTEM`)
+    #[test]
+    #[ignore]
+    fn stock_levels_round_trip()
+    {
+        let system_dir = match ::std::env::var_os(::SYSTEM_DIR_VAR)
+            {
+            Some(v) => ::std::path::PathBuf::from(v),
+            None => panic!("{} must point at the game's data to run this test", ::SYSTEM_DIR_VAR),
+            };
+        let pod = super::super::PodArchive::from_file_mapped(system_dir.join("FURY3.POD")).unwrap();
+        let mut count = 0;
+        for name in pod.file_names()
+        {
+            if !name.ends_with(".DEF") {
+                continue;
+            }
+            let data = pod.file_data(&name).unwrap();
+            let f = EntityFile::from_file(&data[..]).unwrap_or_else(|e| panic!("{}: {}", name, e));
+            let mut out = Vec::new();
+            f.write(&mut out).unwrap();
+            assert!(out[..] == data[..], "{} not reproduced", name);
+            count += 1;
+        }
+        assert!(count > 0, "No .DEF files found");
+    }
+}
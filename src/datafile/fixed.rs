@@ -0,0 +1,195 @@
+//!
+//! Fixed-point numbers, as used by the original game's data files
+//!
+//! Arithmetic wraps on overflow (like the game's own 32-bit integer maths), and division by zero saturates.
+use std::marker::PhantomData;
+
+/// Number of fractional bits in a `Fixed`
+pub trait FracBits: Copy
+{
+    const BITS: u32;
+}
+macro_rules! frac_bits {
+    ($($name:ident = $bits:expr,)*) => { $(
+        #[derive(Copy,Clone,Debug,PartialEq,Eq,PartialOrd,Ord,Hash)]
+        pub struct $name;
+        impl FracBits for $name { const BITS: u32 = $bits; }
+    )* };
+}
+frac_bits! {
+    Frac16 = 16,
+    Frac20 = 20,
+    Frac23 = 23,
+}
+
+/// A signed 32-bit fixed-point number, with `F::BITS` fractional bits
+#[derive(Copy,Clone,PartialEq,Eq,PartialOrd,Ord,Hash)]
+pub struct Fixed<F: FracBits>
+{
+    bits: i32,
+    _frac: PhantomData<F>,
+}
+impl<F: FracBits> Fixed<F>
+{
+    pub fn from_bits(bits: i32) -> Self {
+        Fixed { bits: bits, _frac: PhantomData }
+    }
+    /// Raw value, as stored in the data files
+    pub fn to_bits(self) -> i32 {
+        self.bits
+    }
+
+    pub fn from_int(v: i32) -> Self {
+        Self::from_bits(v.wrapping_shl(F::BITS))
+    }
+    /// Nearest representable value (saturating)
+    #[cfg(test)]
+    pub fn from_f64(v: f64) -> Self {
+        let v = (v * (1u64 << F::BITS) as f64).round();
+        Self::from_bits(v.max(::std::i32::MIN as f64).min(::std::i32::MAX as f64) as i32)
+    }
+
+    pub fn to_f32(self) -> f32 {
+        self.bits as f32 / (1u64 << F::BITS) as f32
+    }
+    /// Exact conversion
+    pub fn to_f64(self) -> f64 {
+        self.bits as f64 / (1u64 << F::BITS) as f64
+    }
+}
+impl<F: FracBits> Default for Fixed<F>
+{
+    fn default() -> Self {
+        Self::from_bits(0)
+    }
+}
+impl<F: FracBits> ::std::fmt::Display for Fixed<F>
+{
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result
+    {
+        // f64 has enough precision to represent every value exactly
+        ::std::fmt::Display::fmt(&self.to_f64(), f)
+    }
+}
+impl<F: FracBits> ::std::fmt::Debug for Fixed<F>
+{
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result
+    {
+        write!(f, "{}({:#x}/2^{})", self.to_f64(), self.bits, F::BITS)
+    }
+}
+
+impl<F: FracBits> ::std::ops::Add for Fixed<F>
+{
+    type Output = Self;
+    fn add(self, o: Self) -> Self { Self::from_bits(self.bits.wrapping_add(o.bits)) }
+}
+impl<F: FracBits> ::std::ops::Sub for Fixed<F>
+{
+    type Output = Self;
+    fn sub(self, o: Self) -> Self { Self::from_bits(self.bits.wrapping_sub(o.bits)) }
+}
+impl<F: FracBits> ::std::ops::Neg for Fixed<F>
+{
+    type Output = Self;
+    fn neg(self) -> Self { Self::from_bits(self.bits.wrapping_neg()) }
+}
+/// Truncate a wide result to 32 bits, wrapping like the narrow operations
+fn wrap(v: i64) -> i32
+{
+    v as i32
+}
+/// Quotient of two (already scaled) values, saturating when dividing by zero
+fn div_bits(n: i64, d: i64) -> i32
+{
+    if d == 0 {
+        match n.signum()
+        {
+        1 => ::std::i32::MAX,
+        -1 => ::std::i32::MIN,
+        _ => 0,
+        }
+    }
+    else {
+        // The numerator is at most 63 bits, so only the final truncation can overflow
+        wrap(n / d)
+    }
+}
+
+impl<F: FracBits> ::std::ops::Mul for Fixed<F>
+{
+    type Output = Self;
+    fn mul(self, o: Self) -> Self { Self::from_bits(wrap((self.bits as i64 * o.bits as i64) >> F::BITS)) }
+}
+impl<F: FracBits> ::std::ops::Div for Fixed<F>
+{
+    type Output = Self;
+    fn div(self, o: Self) -> Self { Self::from_bits(div_bits((self.bits as i64) << F::BITS, o.bits as i64)) }
+}
+impl<F: FracBits> ::std::ops::Mul<i32> for Fixed<F>
+{
+    type Output = Self;
+    fn mul(self, o: i32) -> Self { Self::from_bits(self.bits.wrapping_mul(o)) }
+}
+impl<F: FracBits> ::std::ops::Div<i32> for Fixed<F>
+{
+    type Output = Self;
+    fn div(self, o: i32) -> Self { Self::from_bits(div_bits(self.bits as i64, o as i64)) }
+}
+impl<F: FracBits> ::std::ops::AddAssign for Fixed<F>
+{
+    fn add_assign(&mut self, o: Self) { *self = *self + o; }
+}
+impl<F: FracBits> ::std::ops::SubAssign for Fixed<F>
+{
+    fn sub_assign(&mut self, o: Self) { *self = *self - o; }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::i32::{MIN, MAX};
+
+    type F16 = Fixed<Frac16>;
+    fn f(v: f64) -> F16 { F16::from_f64(v) }
+
+    #[test]
+    fn arithmetic()
+    {
+        assert_eq!(f(1.5) + f(2.25), f(3.75));
+        assert_eq!(f(1.5) - f(2.25), f(-0.75));
+        assert_eq!(f(1.5) * f(-2.5), f(-3.75));
+        assert_eq!(f(-3.75) / f(1.5), f(-2.5));
+        assert_eq!(f(1.5) * 3, f(4.5));
+        assert_eq!(f(4.5) / 3, f(1.5));
+        assert_eq!(F16::from_int(-3).to_bits(), -3 << 16);
+    }
+
+    #[test]
+    fn overflow_wraps()
+    {
+        let max = F16::from_bits(MAX);
+        let min = F16::from_bits(MIN);
+        let one = F16::from_int(1);
+        assert_eq!((max + F16::from_bits(1)).to_bits(), MIN);
+        assert_eq!((min - F16::from_bits(1)).to_bits(), MAX);
+        assert_eq!((-min).to_bits(), MIN);
+        assert_eq!((max * 2).to_bits(), MAX.wrapping_mul(2));
+        assert_eq!(F16::from_int(0x8000) * F16::from_int(2), F16::from_int(0x8000) + F16::from_int(0x8000));
+        assert_eq!((min / -one).to_bits(), MIN);
+        assert_eq!((min / -1).to_bits(), MIN);
+        assert_eq!(F16::from_int(0x10000), F16::from_bits(0));
+    }
+
+    #[test]
+    fn divide_by_zero()
+    {
+        assert_eq!((f(2.) / f(0.)).to_bits(), MAX);
+        assert_eq!((f(-2.) / f(0.)).to_bits(), MIN);
+        assert_eq!((f(0.) / f(0.)).to_bits(), 0);
+        assert_eq!((f(2.) / 0).to_bits(), MAX);
+        assert_eq!((f(-2.) / 0).to_bits(), MIN);
+        assert_eq!((f(0.) / 0).to_bits(), 0);
+    }
+}
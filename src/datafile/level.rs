@@ -0,0 +1,80 @@
+//!
+//! `.LVL` level descriptor (see `docs/datafile_notes/levels.md`)
+//!
+
+/// Parsed `.LVL` file, most entries are file names
+pub struct Level
+{
+    pub unk1: String,
+    /// Briefing information file
+    pub briefing: String,
+    /// Heightmap (`.RAW`, in DATA)
+    pub heightmap: String,
+    /// Heightmap texturing (`.CLR`, in DATA)
+    pub texture_map: String,
+    /// Default texture palette (`.ACT`, in ART)
+    pub palette: String,
+    /// Texture list (`.TEX`, in DATA)
+    pub texture_list: String,
+    pub qke: String,
+    pub pup: String,
+    pub ani: String,
+    /// Tunnel descriptions
+    pub tunnels: String,
+    /// Sky texture (`.RAW`, in ART)
+    pub sky_texture: String,
+    /// Sky texture palette (`.ACT`, in ART)
+    pub sky_palette: String,
+    /// Entity definitions (`.DEF`, in DATA)
+    pub entities: String,
+    pub nav: String,
+    /// Background music (`.MOD`)
+    pub music: String,
+    pub fog: String,
+    pub lte: String,
+    /// Remaining lines (unknown values, movies)
+    pub extra: Vec<String>,
+}
+
+impl Level
+{
+    pub fn from_file<F: ::std::io::Read>(mut file: F) -> ::std::io::Result<Level>
+    {
+        let mut data = String::new();
+        file.read_to_string(&mut data)?;
+        let mut it = data.lines().map(|l| l.trim_right());
+
+        let mut next = |name: &str| match it.next()
+            {
+            Some(v) => Ok(v.to_owned()),
+            None => {
+                warn!("Level file truncated before {}", name);
+                Err(::std::io::Error::new(::std::io::ErrorKind::UnexpectedEof, "Level file truncated"))
+                },
+            };
+        let rv = Level {
+            unk1: next("unk1")?,
+            briefing: next("briefing")?,
+            heightmap: next("heightmap")?,
+            texture_map: next("texture map")?,
+            palette: next("palette")?,
+            texture_list: next("texture list")?,
+            qke: next("qke")?,
+            pup: next("pup")?,
+            ani: next("ani")?,
+            tunnels: next("tunnels")?,
+            sky_texture: next("sky texture")?,
+            sky_palette: next("sky palette")?,
+            entities: next("entities")?,
+            nav: next("nav")?,
+            music: next("music")?,
+            fog: next("fog")?,
+            lte: next("lte")?,
+            extra: Vec::new(),
+            };
+        Ok(Level {
+            extra: it.map(|v| v.to_owned()).collect(),
+            ..rv
+            })
+    }
+}
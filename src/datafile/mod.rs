@@ -3,12 +3,21 @@
 //!
 
 pub use self::pod_file::PodArchive;
-pub use self::pod_file::FileHandle;
-pub use self::model::Model;
+pub use self::pod_file::{FileHandle, Backend};
+pub use self::pod_file::{IntegrityReport, IntegrityProblem};
+pub use self::model::{Model, UnknownBlock};
+pub use self::level::Level;
+pub use self::entities::EntityFile;
+pub use self::fixed::Fixed;
 
 mod pod_file;
 
 mod model;
+mod level;
+pub mod entities;
+pub mod fixed;
+#[cfg(test)]
+pub mod synth;
 
 struct CStrBuf<A>
 {
@@ -18,20 +27,23 @@ impl<A> CStrBuf<A>
 where
     A: AsRef<[u8]>
 {
-    fn new(buf: A) -> CStrBuf<A>
+    /// Returns `None` if there's no NUL terminator in the buffer
+    fn new(buf: A) -> Option<CStrBuf<A>>
     {
-        assert!( buf.as_ref().iter().position(|&v| v == 0).is_some() );
-        CStrBuf {
+        if buf.as_ref().iter().position(|&v| v == 0).is_none() {
+            return None;
+        }
+        Some(CStrBuf {
             buf: buf,
-            }
+            })
     }
 
     fn read_from_file<F: ::std::io::Read>(fp: &mut F, mut buf: A) -> ::std::io::Result<Self>
     where
         A: AsMut<[u8]>
     {
-        fp.read(buf.as_mut())?;
-        Ok( CStrBuf::new(buf) )
+        fp.read_exact(buf.as_mut())?;
+        CStrBuf::new(buf).ok_or_else(|| ::std::io::Error::new(::std::io::ErrorKind::InvalidData, "String not NUL terminated"))
     }
 
     fn as_bytes(&self) -> &[u8]
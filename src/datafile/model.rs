@@ -1,16 +1,54 @@
+use super::fixed::{Fixed, Frac16, Frac23};
+
 pub struct Model
 {
-    pub vertices: Vec<[f32; 3]>,
+    /// Vertex scale factor (multiplied with the raw vertex values)
+    pub scale: Fixed<Frac23>,
+    /// Raw vertex positions
+    pub vertices: Vec<[i32; 3]>,
     pub faces: Vec<Face>,
 }
 pub struct Face
 {
     pub v: [usize; 3],
-    pub normal: [f32; 3],
+    pub normal: [Fixed<Frac16>; 3],
+}
+
+fn invalid(message: String) -> ::std::io::Error
+{
+    ::std::io::Error::new(::std::io::ErrorKind::InvalidData, message)
+}
+
+/// A block type that the parser doesn't know about, wrapped in an `InvalidData` IO error
+#[derive(Debug)]
+pub struct UnknownBlock
+{
+    pub id: u32,
+}
+impl ::std::fmt::Display for UnknownBlock
+{
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result
+    {
+        write!(f, "Unknown block 0x{:02x}", self.id)
+    }
+}
+impl ::std::error::Error for UnknownBlock
+{
+    fn description(&self) -> &str {
+        "Unknown .BIN block"
+    }
 }
 
 impl Model
 {
+    /// Model-space position of a vertex
+    pub fn vertex_position(&self, idx: usize) -> [f32; 3]
+    {
+        let v = self.vertices[idx];
+        let s = self.scale.to_f32();
+        [ v[0] as f32 * s, v[1] as f32 * s, v[2] as f32 * s ]
+    }
+
     pub fn from_bin_file<F: ::std::io::Read>(mut file: F) -> ::std::io::Result<Model>
     {
         use byteorder::ReadBytesExt;
@@ -19,11 +57,11 @@ impl Model
         let id = file.read_u32::<LittleEndian>()?;
         if id == 0x20 {
             // TODO: return a special error that indicates that it's a animation file, not a model.
-            return Ok(Model { vertices: vec![ [0.; 3]], faces: vec![Face { v: [0,0,0], normal: [0.; 3] }] });
+            return Ok(Model { scale: Fixed::from_int(1), vertices: vec![ [0; 3]], faces: vec![Face { v: [0,0,0], normal: [Fixed::default(); 3] }] });
         }
         if id != 0x14 {
             warn!(".BIN file ID not 0x14, instead {:#x}", id);
-            return Err(::std::io::Error::new(::std::io::ErrorKind::InvalidData, "File ID not 0x14"))
+            return Err(invalid(format!("File ID not 0x14, instead {:#x}", id)));
         }
 
         let scale = file.read_u32::<LittleEndian>()?;
@@ -32,13 +70,12 @@ impl Model
         let num_vert = file.read_u32::<LittleEndian>()?;
         debug!("Scale: {:#x}", scale);
 
-	    let fscale = scale as f32 / 0x80_0000 as f32;
         let mut vertices = Vec::new();
         for _ in 0 .. num_vert
         {
-            let x = file.read_i32::<LittleEndian>()? as f32 * fscale;
-            let y = file.read_i32::<LittleEndian>()? as f32 * fscale;
-            let z = file.read_i32::<LittleEndian>()? as f32 * fscale;
+            let x = file.read_i32::<LittleEndian>()?;
+            let y = file.read_i32::<LittleEndian>()?;
+            let z = file.read_i32::<LittleEndian>()?;
             vertices.push([x, y, z]);
         }
 
@@ -67,8 +104,9 @@ impl Model
                 let texture_name = super::CStrBuf::read_from_file(&mut file, [0u8; 16])?;
                 debug!("0x0D: texture_name={:?}", &*texture_name);
                 },
-            // 0x0E => Faces
-            0x0E | 0x18 => {
+            // 0x0E => Faces (with texture coordinates)
+            // 0x19 => Special faces (no texture coordinates)
+            0x0E | 0x18 | 0x19 => {
                 let nvert = file.read_u32::<LittleEndian>()?;
                 let normal_x = file.read_i32::<LittleEndian>()?;
                 let normal_y = file.read_i32::<LittleEndian>()?;
@@ -77,116 +115,45 @@ impl Model
                 debug!("0x{:2x}: Faces ({} pts)", block_id, nvert);
 
                 let normal = [
-                    normal_x as f32 / 65535.0,
-                    normal_y as f32 / 65535.0,
-                    normal_z as f32 / 65535.0,
+                    Fixed::from_bits(normal_x),
+                    Fixed::from_bits(normal_y),
+                    Fixed::from_bits(normal_z),
                     ];
-                
-                if nvert == 3
-                {
-                    let mut face_indexes = [0,0,0];
-                    for slot in face_indexes.iter_mut()
-                    {
-                        let idx = file.read_u32::<LittleEndian>()?;
-                        let _tex_u = file.read_u32::<LittleEndian>()?;
-                        let _tex_v = file.read_u32::<LittleEndian>()?;
-                        if !(idx < num_vert) {
-                            error!("Vertex index {} out of range (max {})", idx, num_vert);
-                            continue ;
-                        }
-                        *slot = idx as usize;
-                    }
-                    faces.push(Face {
-                        v: face_indexes,
-                        normal: normal,
-                        });
+                if nvert != 3 && nvert != 4 {
+                    // TODO: Are there other polygon sizes?
+                    return Err(invalid(format!("Strange number of points in face - {}", nvert)));
                 }
-                else if nvert == 4
+
+                let mut fi = [0,0,0,0];
+                let mut valid = true;
+                for slot in fi[..nvert as usize].iter_mut()
                 {
-                    let mut fi = [0,0,0,0];
-                    for slot in fi.iter_mut()
-                    {
-                        let idx = file.read_u32::<LittleEndian>()?;
+                    let idx = file.read_u32::<LittleEndian>()?;
+                    if block_id != 0x19 {
                         let _tex_u = file.read_u32::<LittleEndian>()?;
                         let _tex_v = file.read_u32::<LittleEndian>()?;
-                        if !(idx < num_vert) {
-                            error!("Vertex index {} out of range (max {})", idx, num_vert);
-                            continue ;
-                        }
-                        *slot = idx as usize;
                     }
-
-                    faces.push(Face {
-                        v: [fi[0], fi[1], fi[2]],
-                        normal: normal,
-                        });
-                    faces.push(Face {
-                        // Ordering matters
-                        v: [fi[2], fi[3], fi[0]],
-                        normal: normal,
-                        });
-                }
-                else
-                {
-                    panic!("TODO: Strange number of points in face - {}", nvert);
-                }
-                },
-            // Special faces
-            0x19 => {
-                let nvert = file.read_u32::<LittleEndian>()?;
-                let normal_x = file.read_i32::<LittleEndian>()?;
-                let normal_y = file.read_i32::<LittleEndian>()?;
-                let normal_z = file.read_i32::<LittleEndian>()?;
-                let _magic = file.read_u32::<LittleEndian>()?;
-                debug!("0x{:2x}: Faces ({} pts)", block_id, nvert);
-
-                let normal = [
-                    normal_x as f32 / 65535.0,
-                    normal_y as f32 / 65535.0,
-                    normal_z as f32 / 65535.0,
-                    ];
-                
-                if nvert == 3
-                {
-                    let mut face_indexes = [0,0,0];
-                    for slot in face_indexes.iter_mut()
-                    {
-                        let idx = file.read_u32::<LittleEndian>()?;
-                        if !(idx < num_vert) {
-                            panic!("Vertex index {} out of range (max {})", idx, num_vert);
-                        }
-                        *slot = idx as usize;
+                    if !(idx < num_vert) {
+                        error!("Vertex index {} out of range (max {})", idx, num_vert);
+                        valid = false;
                     }
-                    faces.push(Face {
-                        v: face_indexes,
-                        normal: normal,
-                        });
+                    *slot = idx as usize;
                 }
-                else if nvert == 4
+                // Faces referencing missing vertices are dropped (the rest of the model is still usable)
+                if valid
                 {
-                    let mut fi = [0,0,0,0];
-                    for slot in fi.iter_mut()
-                    {
-                        let idx = file.read_u32::<LittleEndian>()?;
-                        if !(idx < num_vert) {
-                            panic!("Vertex index {} out of range (max {})", idx, num_vert);
-                        }
-                        *slot = idx as usize;
-                    }
-
                     faces.push(Face {
                         v: [fi[0], fi[1], fi[2]],
                         normal: normal,
                         });
-                    faces.push(Face {
-                        // Ordering matters
-                        v: [fi[2], fi[3], fi[0]],
-                        normal: normal,
-                        });
-                }
-                else
-                {
-                    panic!("TODO: Strange number of points in face - {}", nvert);
+                    if nvert == 4
+                    {
+                        faces.push(Face {
+                            // Ordering matters
+                            v: [fi[2], fi[3], fi[0]],
+                            normal: normal,
+                            });
+                    }
                 }
                 },
             // 0x17 : Unknown purpose
@@ -207,12 +174,14 @@ impl Model
                         };
                     debug!("+{} {:08x}", i, word);
                 }
-                panic!("TODO: MTM block 0x{:02x}", block_id)
+                // TODO: MTM block
+                return Err(::std::io::Error::new(::std::io::ErrorKind::InvalidData, UnknownBlock { id: block_id }));
                 },
             }
         }
 
         Ok(Model {
+            scale: Fixed::from_bits(scale as i32),
             vertices: vertices,
             faces: faces,
             })
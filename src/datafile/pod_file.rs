@@ -1,10 +1,127 @@
 pub struct PodArchive
 {
-    file: ::std::fs::File,
+    data: Box<Backend>,
+    /// Length of the archive, which all entries must lie within
+    file_len: u64,
+    /// Size of the header and file table
+    table_len: u64,
     /// Sorted list of files
     files: Vec<FileEnt>,
 }
 
+/// Storage behind a `PodArchive`
+///
+/// Reads are positioned (there's no shared cursor), so any number of files in the archive can be open at once, from
+/// any thread.
+pub trait Backend: Send + Sync
+{
+    fn len(&self) -> u64;
+    /// Read from an absolute position, returning the number of bytes read (zero at the end)
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> ::std::io::Result<usize>;
+    /// The whole archive, if it's in memory (files can then be borrowed instead of copied)
+    fn as_slice(&self) -> Option<&[u8]> {
+        None
+    }
+}
+
+/// Archive read from an open file
+struct FileBackend
+{
+    file: ::std::fs::File,
+    len: u64,
+    /// Held while seeking and reading, on targets without positioned reads
+    #[cfg(not(any(unix, windows)))]
+    cursor: ::std::sync::Mutex<()>,
+}
+impl FileBackend
+{
+    fn new(file: ::std::fs::File) -> ::std::io::Result<FileBackend>
+    {
+        Ok(FileBackend {
+            len: file.metadata()?.len(),
+            file: file,
+            #[cfg(not(any(unix, windows)))]
+            cursor: ::std::sync::Mutex::new(()),
+            })
+    }
+}
+impl Backend for FileBackend
+{
+    fn len(&self) -> u64 {
+        self.len
+    }
+    #[cfg(unix)]
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> ::std::io::Result<usize> {
+        ::std::os::unix::fs::FileExt::read_at(&self.file, buf, pos)
+    }
+    #[cfg(windows)]
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> ::std::io::Result<usize> {
+        // NOTE: This moves the file cursor, but nothing else uses it
+        ::std::os::windows::fs::FileExt::seek_read(&self.file, buf, pos)
+    }
+    #[cfg(not(any(unix, windows)))]
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> ::std::io::Result<usize> {
+        use std::io::{Read, Seek, SeekFrom};
+        let _lock = self.cursor.lock().unwrap_or_else(|e| e.into_inner());
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(pos))?;
+        file.read(buf)
+    }
+}
+
+/// Archive mapped into memory
+struct MappedBackend(::memmap::Mmap);
+impl Backend for MappedBackend
+{
+    fn len(&self) -> u64 {
+        self.0.len() as u64
+    }
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> ::std::io::Result<usize> {
+        Ok(read_slice_at(&self.0, pos, buf))
+    }
+    fn as_slice(&self) -> Option<&[u8]> {
+        Some(&self.0)
+    }
+}
+
+/// Archive already in memory (e.g. a `Vec<u8>` or a `&'static [u8]`)
+struct MemoryBackend<B>(B);
+impl<B: AsRef<[u8]> + Send + Sync> Backend for MemoryBackend<B>
+{
+    fn len(&self) -> u64 {
+        self.0.as_ref().len() as u64
+    }
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> ::std::io::Result<usize> {
+        Ok(read_slice_at(self.0.as_ref(), pos, buf))
+    }
+    fn as_slice(&self) -> Option<&[u8]> {
+        Some(self.0.as_ref())
+    }
+}
+
+fn read_slice_at(data: &[u8], pos: u64, buf: &mut [u8]) -> usize
+{
+    let start = ::std::cmp::min(pos, data.len() as u64) as usize;
+    let len = ::std::cmp::min(buf.len(), data.len() - start);
+    buf[..len].copy_from_slice(&data[start .. start + len]);
+    len
+}
+
+fn read_exact_at(data: &Backend, mut pos: u64, mut buf: &mut [u8]) -> ::std::io::Result<()>
+{
+    while !buf.is_empty()
+    {
+        let n = data.read_at(pos, buf)?;
+        if n == 0 {
+            return Err(::std::io::Error::new(::std::io::ErrorKind::UnexpectedEof, "Unexpected end of archive"));
+        }
+        pos += n as u64;
+        let tmp = buf;
+        buf = &mut tmp[n..];
+    }
+    Ok( () )
+}
+
 struct FileEnt
 {
     name: super::CStrBuf<[u8; 32]>,
@@ -17,64 +134,250 @@ impl ::std::fmt::Debug for FileEnt {
         write!(f, "{:?}@{:#x}+{:#x}", &*self.name, self.offset, self.size)
     }
 }
+impl FileEnt
+{
+    fn end(&self) -> u64 {
+        self.offset as u64 + self.size as u64
+    }
+}
+
+/// A problem found by `PodArchive::check`
+#[derive(Debug)]
+pub enum IntegrityProblem
+{
+    /// Entry extends past the end of the archive (it can't be opened)
+    Truncated { name: String, offset: u32, size: u32 },
+    /// Entry overlaps the header and file table
+    OverlapsTable { name: String },
+    /// Two entries share some of their data
+    Overlap { first: String, second: String },
+    ZeroLength { name: String },
+}
+impl ::std::fmt::Display for IntegrityProblem
+{
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result
+    {
+        match *self
+        {
+        IntegrityProblem::Truncated { ref name, offset, size } => write!(f, "{}: {:#x}+{:#x} extends past the end of the archive", name, offset, size),
+        IntegrityProblem::OverlapsTable { ref name } => write!(f, "{}: overlaps the file table", name),
+        IntegrityProblem::Overlap { ref first, ref second } => write!(f, "{} overlaps {}", first, second),
+        IntegrityProblem::ZeroLength { ref name } => write!(f, "{}: zero length", name),
+        }
+    }
+}
+
+/// Result of `PodArchive::check`
+#[derive(Debug)]
+pub struct IntegrityReport
+{
+    pub file_count: usize,
+    pub problems: Vec<IntegrityProblem>,
+}
+impl IntegrityReport
+{
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Size of the archive header (file count and comment)
+const HEADER_SIZE: u64 = 4 + 0x50;
+/// Size of a file table entry (name, size, offset)
+const ENTRY_SIZE: u64 = 32 + 4 + 4;
 
 impl PodArchive
 {
+    /// Open an archive, reading files from it as needed
     pub fn from_file<P: AsRef<::std::path::Path>>(path: P) -> ::std::io::Result<PodArchive>
     {
+        let file = ::std::fs::File::open(path.as_ref())?;
+        debug!("Loading {}", path.as_ref().display());
+        PodArchive::from_backend(Box::new(FileBackend::new(file)?))
+    }
+
+    /// Open an archive by mapping it into memory, or reading files from it as needed if it can't be mapped
+    pub fn from_file_mapped<P: AsRef<::std::path::Path>>(path: P) -> ::std::io::Result<PodArchive>
+    {
+        let file = ::std::fs::File::open(path.as_ref())?;
+        debug!("Mapping {}", path.as_ref().display());
+        // SAFETY: The archive is assumed to not be modified while the game is running
+        match unsafe { ::memmap::Mmap::map(&file) }
+        {
+        Ok(map) => PodArchive::from_backend(Box::new(MappedBackend(map))),
+        Err(e) => {
+            warn!("Unable to map {}, reading it instead: {}", path.as_ref().display(), e);
+            PodArchive::from_backend(Box::new(FileBackend::new(file)?))
+            },
+        }
+    }
 
-        use std::io::Read;
+    /// Archive from data already in memory
+    pub fn from_bytes<B: AsRef<[u8]> + Send + Sync + 'static>(data: B) -> ::std::io::Result<PodArchive>
+    {
+        PodArchive::from_backend(Box::new(MemoryBackend(data)))
+    }
+
+    pub fn from_backend(data: Box<Backend>) -> ::std::io::Result<PodArchive>
+    {
         use byteorder::ReadBytesExt;
         use byteorder::LittleEndian;
-        let mut fp = ::std::fs::File::open(path.as_ref())?;
-
-        // - Read header
-        let file_count = fp.read_u32::<LittleEndian>()?;
-        let _comment = {
-            let mut buf = [0; 0x50];
-            fp.read(&mut buf)?;
-            //Ok( super::CStrBuf::new(&buf) )
-            buf
-            };
-        debug!("Loading {} files from {}", file_count, path.as_ref().display());
+        let file_len = data.len();
+
+        // - Read header (file count and comment)
+        let mut header = [0; HEADER_SIZE as usize];
+        read_exact_at(&*data, 0, &mut header)?;
+        let file_count = (&header[..]).read_u32::<LittleEndian>()?;
+        debug!("{} files", file_count);
+        // Check the count against the file size before reading (so a bad count fails quickly)
+        let table_len = HEADER_SIZE + file_count as u64 * ENTRY_SIZE;
+        if table_len > file_len {
+            return Err(::std::io::Error::new(::std::io::ErrorKind::InvalidData, format!("File table ({} entries) larger than the archive", file_count)));
+        }
+        let mut table = vec![0; (table_len - HEADER_SIZE) as usize];
+        read_exact_at(&*data, HEADER_SIZE, &mut table)?;
 
         // Enumerate files
+        let mut fp = &table[..];
         let mut files = Vec::new();
         for _ in 0 .. file_count
         {
-            files.push(FileEnt {
+            let ent = FileEnt {
                 name: super::CStrBuf::read_from_file(&mut fp, [0; 32])?,
                 size: fp.read_u32::<LittleEndian>()?,
                 offset: fp.read_u32::<LittleEndian>()?,
-                });
+                };
+            // Kept so `check` can report it, but opening it will fail
+            if ent.end() > file_len {
+                warn!("{:?} extends past the end of the archive ({:#x})", ent, file_len);
+            }
+            files.push(ent);
         }
 
         files.sort_by(|a,b| ::std::cmp::Ord::cmp(&*a.name, &*b.name));
 
         Ok(PodArchive{
-            file: fp,
+            data: data,
+            file_len: file_len,
+            table_len: table_len,
             files: files,
             })
     }
 
-    pub fn open_file<'s>(&'s mut self, path: &str) -> ::std::io::Result<FileHandle<'s>>
+    /// Check the file table for entries that are out of bounds, overlapping, or empty
+    pub fn check(&self) -> IntegrityReport
     {
-        let idx = match self.files.binary_search_by_key(&path.as_bytes(), |v| v.name.as_bytes())
-            {
-            Ok(i) => i,
-            Err(_) => return Err(::std::io::Error::new(::std::io::ErrorKind::NotFound, "")),
-            };
-        use std::io::Seek;
-        self.file.seek(::std::io::SeekFrom::Start(self.files[idx].offset as u64))?;
+        let name = |e: &FileEnt| e.name.to_string_lossy().into_owned();
+        let mut problems = Vec::new();
+        for e in &self.files
+        {
+            if e.end() > self.file_len {
+                problems.push(IntegrityProblem::Truncated { name: name(e), offset: e.offset, size: e.size });
+            }
+            if e.size == 0 {
+                problems.push(IntegrityProblem::ZeroLength { name: name(e) });
+            }
+            else if (e.offset as u64) < self.table_len {
+                problems.push(IntegrityProblem::OverlapsTable { name: name(e) });
+            }
+        }
+
+        // Sort by position, and compare each entry against the furthest-reaching one before it
+        let mut by_offset: Vec<_> = self.files.iter().filter(|e| e.size > 0).collect();
+        by_offset.sort_by_key(|e| e.offset);
+        let mut furthest: Option<&FileEnt> = None;
+        for e in by_offset
+        {
+            if let Some(prev) = furthest {
+                if (e.offset as u64) < prev.end() {
+                    problems.push(IntegrityProblem::Overlap { first: name(prev), second: name(e) });
+                }
+            }
+            if furthest.map(|p| e.end() > p.end()).unwrap_or(true) {
+                furthest = Some(e);
+            }
+        }
+
+        IntegrityReport {
+            file_count: self.files.len(),
+            problems: problems,
+        }
+    }
+
+    /// Full names (e.g. `DATA\EGYPT.RAW`) of all files in the archive, in sorted order
+    pub fn file_names(&self) -> Vec<String>
+    {
+        self.files.iter().map(|v| v.name.to_string_lossy().into_owned()).collect()
+    }
+
+    pub fn open_file(&self, path: &str) -> ::std::io::Result<FileHandle>
+    {
+        let idx = self.find_file(path)?;
+        self.open_idx(idx)
+    }
+    pub fn open_dir_file(&self, dir: &str, file: &str) -> ::std::io::Result<FileHandle>
+    {
+        let idx = self.find_dir_file(dir, file)?;
+        self.open_idx(idx)
+    }
+
+    /// Contents of a file, borrowed from the archive if it's in memory
+    pub fn file_data(&self, path: &str) -> ::std::io::Result<::std::borrow::Cow<[u8]>>
+    {
+        let idx = self.find_file(path)?;
+        self.data_idx(idx)
+    }
+    pub fn dir_file_data(&self, dir: &str, file: &str) -> ::std::io::Result<::std::borrow::Cow<[u8]>>
+    {
+        let idx = self.find_dir_file(dir, file)?;
+        self.data_idx(idx)
+    }
+
+    fn open_idx(&self, idx: usize) -> ::std::io::Result<FileHandle>
+    {
+        let ent = self.entry(idx)?;
         Ok(FileHandle {
-            file: &mut self.file,
+            data: &*self.data,
+            offset: ent.offset as u64,
             cur_pos: 0,
-            size: self.files[idx].size,
+            size: ent.size,
             })
     }
-    pub fn open_dir_file<'s>(&'s mut self, dir: &str, file: &str) -> ::std::io::Result<FileHandle<'s>>
+    fn data_idx(&self, idx: usize) -> ::std::io::Result<::std::borrow::Cow<[u8]>>
     {
-        debug!("open_dir_file({:?}, {:?})", dir, file);
+        let ent = self.entry(idx)?;
+        let range = ent.offset as usize .. ent.end() as usize;
+        Ok(match self.data.as_slice()
+        {
+        Some(data) => ::std::borrow::Cow::Borrowed(&data[range]),
+        None => {
+            let mut buf = vec![0; ent.size as usize];
+            read_exact_at(&*self.data, ent.offset as u64, &mut buf)?;
+            ::std::borrow::Cow::Owned(buf)
+            },
+        })
+    }
+    /// Entry for a file, checking that it can be read
+    fn entry(&self, idx: usize) -> ::std::io::Result<&FileEnt>
+    {
+        let ent = &self.files[idx];
+        if ent.end() > self.file_len {
+            return Err(::std::io::Error::new(::std::io::ErrorKind::UnexpectedEof, format!("{:?} extends past the end of the archive", ent)));
+        }
+        Ok(ent)
+    }
+
+    fn find_file(&self, path: &str) -> ::std::io::Result<usize>
+    {
+        match self.files.binary_search_by_key(&path.as_bytes(), |v| v.name.as_bytes())
+        {
+        Ok(i) => Ok(i),
+        Err(_) => Err(::std::io::Error::new(::std::io::ErrorKind::NotFound, "")),
+        }
+    }
+    fn find_dir_file(&self, dir: &str, file: &str) -> ::std::io::Result<usize>
+    {
+        debug!("find_dir_file({:?}, {:?})", dir, file);
         let dir = dir.as_bytes();
         let file = file.as_bytes();
         let mut ofs = 0;
@@ -99,28 +402,25 @@ impl PodArchive
                 {
                 Ok(i) => i,
                 Err(_) => {
-                    warn!("Not found at +{} '{}' - {:?} {:?} -- {:?}", ofs, b as char, range,
-                        &*self.files[range.start].name,
-                        &*self.files[range.end-1].name,
-                        );
+                    if range.start < range.end {
+                        warn!("Not found at +{} '{}' - {:?} {:?} -- {:?}", ofs, b as char, range,
+                            &*self.files[range.start].name,
+                            &*self.files[range.end-1].name,
+                            );
+                    }
                     return Err(::std::io::Error::new(::std::io::ErrorKind::NotFound, ""))
                     },
                 };
+            // Widen to all entries with this byte (the binary search can land anywhere in the run)
+            let key = |idx: usize| *self.files[idx].name.as_bytes().get(ofs).unwrap_or(&255);
             let mut s = range.start + i;
-            while s > 0 && s >= range.start && b == *self.files[s].name.as_bytes().get(ofs).unwrap_or(&255) {
+            while s > range.start && key(s - 1) == b {
                 s -= 1;
             }
-            if s > 0 {
-                s += 1;
-            }
-            assert_eq!( b, self.files[s].name.as_bytes()[ofs] );
-            let mut e = range.start + i;
-            while e < range.end && b == *self.files[e].name.as_bytes().get(ofs).unwrap_or(&255) {
+            let mut e = range.start + i + 1;
+            while e < range.end && key(e) == b {
                 e += 1;
             }
-            if e < range.end {
-                assert_eq!( b, self.files[e-1].name.as_bytes()[ofs] );
-            }
 
             range = s .. e;
             ofs += 1;
@@ -129,20 +429,16 @@ impl PodArchive
         if ofs != self.files[idx].name.as_bytes().len() {
             return Err(::std::io::Error::new(::std::io::ErrorKind::NotFound, ""));
         }
-        
-        use std::io::Seek;
-        self.file.seek(::std::io::SeekFrom::Start(self.files[idx].offset as u64))?;
-        Ok(FileHandle {
-            file: &mut self.file,
-            cur_pos: 0,
-            size: self.files[idx].size,
-            })
+        Ok(idx)
     }
 }
 
+/// An open file within a `PodArchive`
 pub struct FileHandle<'a>
 {
-    file: &'a mut ::std::fs::File,
+    data: &'a Backend,
+    /// Start of the file within the archive
+    offset: u64,
     cur_pos: u32,
     size: u32,
 }
@@ -156,7 +452,7 @@ impl<'a> ::std::io::Read for FileHandle<'a>
 {
     fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize>
     {
-        let space = (self.size - self.cur_pos) as usize;
+        let space = self.size.saturating_sub(self.cur_pos) as usize;
 
         let buf = if buf.len() > space {
                 &mut buf[..space]
@@ -164,8 +460,125 @@ impl<'a> ::std::io::Read for FileHandle<'a>
             else {
                 buf
             };
-        let rv = self.file.read(buf)?;
+        let rv = self.data.read_at(self.offset + self.cur_pos as u64, buf)?;
+        // `rv` is at most `space`, so this can't pass `size`
         self.cur_pos += rv as u32;
         Ok(rv)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::io::Read;
+    use super::*;
+    use super::super::synth::PodBuilder;
+
+    /// Archive with the given files, with the file table then edited by `patch` (called with the data, and the offset of
+    /// each entry's size field, in `add` order)
+    fn patched<F: FnOnce(&mut Vec<u8>, &[usize])>(files: &[(&str, &[u8])], patch: F) -> Vec<u8>
+    {
+        let mut pod = PodBuilder::new("Test");
+        for &(name, data) in files {
+            pod.add(name, data.to_vec());
+        }
+        let mut data = Vec::new();
+        pod.write(&mut data).unwrap();
+        let entries: Vec<_> = (0 .. files.len()).map(|i| HEADER_SIZE as usize + i * ENTRY_SIZE as usize + 32).collect();
+        patch(&mut data, &entries);
+        data
+    }
+    fn get_u32(data: &[u8], ofs: usize) -> u32
+    {
+        use byteorder::{ByteOrder, LittleEndian};
+        LittleEndian::read_u32(&data[ofs..])
+    }
+    fn set_u32(data: &mut [u8], ofs: usize, v: u32)
+    {
+        use byteorder::{ByteOrder, LittleEndian};
+        LittleEndian::write_u32(&mut data[ofs..], v)
+    }
+
+    #[test]
+    fn integrity_problems()
+    {
+        let files: &[(&str, &[u8])] = &[ ("A", b"0123456789"), ("B", b"0123456789"), ("C", b"0123456789"), ("D", b"0123456789") ];
+        let data = patched(files, |data, e| {
+            // A starts in the header, C starts half way through B, and D runs off the end
+            set_u32(data, e[0] + 4, 4);
+            let b_offset = get_u32(data, e[1] + 4);
+            set_u32(data, e[2] + 4, b_offset + 5);
+            set_u32(data, e[3], 100);
+            });
+        let pod = PodArchive::from_bytes(data).unwrap();
+        let report = pod.check();
+        assert_eq!(report.file_count, 4);
+        let mut problems: Vec<_> = report.problems.iter().map(|p| p.to_string()).collect();
+        problems.sort();
+        let d_offset = HEADER_SIZE as u32 + 4 * ENTRY_SIZE as u32 + 30;
+        assert_eq!(problems, vec![
+            "A: overlaps the file table".to_owned(),
+            "B overlaps C".to_owned(),
+            format!("D: {:#x}+0x64 extends past the end of the archive", d_offset),
+            ]);
+        assert!(!report.is_ok());
+
+        // The untouched archive is fine
+        let pod = PodArchive::from_bytes(patched(files, |_, _| ())).unwrap();
+        assert!(pod.check().is_ok());
+    }
+
+    /// An entry past the end of the archive can't be opened or read, from any backend
+    #[test]
+    fn truncated_entry_rejected()
+    {
+        let data = patched(&[ ("A", b"abcd"), ("B", b"efgh") ], |data, e| set_u32(data, e[1], 5));
+        let path = ::std::env::temp_dir().join(format!("fury3clone-truncated-{}.pod", ::std::process::id()));
+        ::std::fs::write(&path, &data).unwrap();
+        let archives = [ PodArchive::from_bytes(data).unwrap(), PodArchive::from_file(&path).unwrap(), PodArchive::from_file_mapped(&path).unwrap() ];
+        ::std::fs::remove_file(&path).unwrap();
+
+        for pod in &archives
+        {
+            assert_eq!(pod.open_file("B").err().map(|e| e.kind()), Some(::std::io::ErrorKind::UnexpectedEof));
+            assert_eq!(pod.file_data("B").err().map(|e| e.kind()), Some(::std::io::ErrorKind::UnexpectedEof));
+            assert_eq!(&pod.file_data("A").unwrap()[..], b"abcd");
+        }
+    }
+
+    /// An empty file can't be mapped, so it's read instead (and fails as a short archive, not with the mapping error)
+    #[test]
+    fn unmappable_file_is_read()
+    {
+        let path = ::std::env::temp_dir().join(format!("fury3clone-empty-{}.pod", ::std::process::id()));
+        ::std::fs::write(&path, b"").unwrap();
+        let rv = PodArchive::from_file_mapped(&path);
+        ::std::fs::remove_file(&path).unwrap();
+        assert_eq!(rv.err().map(|e| e.kind()), Some(::std::io::ErrorKind::UnexpectedEof));
+    }
+
+    /// Reading a file stops at its size, even with the next file's data straight after it
+    #[test]
+    fn read_stops_at_size()
+    {
+        let data = patched(&[ ("A", b"abcd"), ("B", b"efgh") ], |_, _| ());
+        let path = ::std::env::temp_dir().join(format!("fury3clone-read-{}.pod", ::std::process::id()));
+        ::std::fs::write(&path, &data).unwrap();
+        let archives = [ PodArchive::from_bytes(data).unwrap(), PodArchive::from_file(&path).unwrap() ];
+        ::std::fs::remove_file(&path).unwrap();
+
+        for pod in &archives
+        {
+            let mut fh = pod.open_file("A").unwrap();
+            let mut buf = [0; 16];
+            assert_eq!(fh.read(&mut buf[..3]).unwrap(), 3);
+            assert_eq!(fh.read(&mut buf).unwrap(), 1);
+            assert_eq!(buf[0], b'd');
+            assert_eq!(fh.read(&mut buf).unwrap(), 0);
+
+            let mut all = Vec::new();
+            pod.open_file("A").unwrap().read_to_end(&mut all).unwrap();
+            assert_eq!(all, b"abcd");
+        }
+    }
+}
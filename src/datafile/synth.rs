@@ -0,0 +1,400 @@
+//!
+//! Synthetic data files and POD archives, so the tests can load a level without the retail data
+//!
+//! The generated files are small and regular, but valid input for all of the parsers and level loaders.
+use std::io::Write;
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use super::fixed::{Fixed, Frac16};
+use super::entities::{EntityFile, EntityType, EntityPlacement};
+
+/// Size of the synthetic heightmap
+pub const MAP_DIM: usize = 128;
+/// Size of the synthetic textures (every other one is half this, so the atlas has mixed sizes to pack)
+pub const TEXTURE_DIM: usize = 32;
+/// Number of synthetic terrain textures
+pub const TEXTURE_COUNT: usize = 4;
+
+/// In-memory POD archive, written out by `write` (or opened directly with `to_archive`)
+pub struct PodBuilder
+{
+    comment: String,
+    files: Vec<(String, Vec<u8>)>,
+}
+impl PodBuilder
+{
+    pub fn new(comment: &str) -> PodBuilder
+    {
+        PodBuilder {
+            comment: comment.to_owned(),
+            files: Vec::new(),
+        }
+    }
+
+    /// Add a file, `path` is the full name within the archive (e.g. `DATA\EGYPT.RAW`)
+    pub fn add(&mut self, path: &str, data: Vec<u8>) -> &mut Self
+    {
+        // Names are stored in upper case (lookups are case-insensitive)
+        self.files.push( (path.to_ascii_uppercase(), data) );
+        self
+    }
+
+    pub fn write<W: Write>(&self, mut out: W) -> ::std::io::Result<()>
+    {
+        // Header is the file count and a 0x50 byte comment, followed by 40 byte entries (name, size, offset)
+        const HEADER_SIZE: usize = 4 + 0x50;
+        const ENTRY_SIZE: usize = 32 + 4 + 4;
+
+        out.write_u32::<LittleEndian>(self.files.len() as u32)?;
+        let mut comment = [0u8; 0x50];
+        let len = ::std::cmp::min(self.comment.len(), comment.len() - 1);
+        comment[..len].copy_from_slice(&self.comment.as_bytes()[..len]);
+        out.write_all(&comment)?;
+
+        let mut offset = HEADER_SIZE + ENTRY_SIZE * self.files.len();
+        for &(ref name, ref data) in &self.files
+        {
+            let mut name_buf = [0u8; 32];
+            if name.len() >= name_buf.len() {
+                return Err(::std::io::Error::new(::std::io::ErrorKind::InvalidInput, format!("File name {:?} too long", name)));
+            }
+            name_buf[..name.len()].copy_from_slice(name.as_bytes());
+            out.write_all(&name_buf)?;
+            out.write_u32::<LittleEndian>(data.len() as u32)?;
+            out.write_u32::<LittleEndian>(offset as u32)?;
+            offset += data.len();
+        }
+        for &(_, ref data) in &self.files
+        {
+            out.write_all(data)?;
+        }
+        Ok( () )
+    }
+
+    /// Build the archive in memory, without writing it to disk
+    pub fn to_archive(&self) -> ::std::io::Result<super::PodArchive>
+    {
+        let mut data = Vec::new();
+        self.write(&mut data)?;
+        super::PodArchive::from_bytes(data)
+    }
+}
+
+/// A `.BIN` model: a square-based pyramid `size` raw units across (with a scale of 1.0)
+///
+/// The sides are triangle blocks and the base is a quad block, so both face paths of the parser are covered.
+pub fn model_bin(size: i32) -> Vec<u8>
+{
+    let h = size / 2;
+    let vertices = [ [-h, 0, -h], [h, 0, -h], [h, 0, h], [-h, 0, h], [0, size, 0] ];
+    let normal = |n: [f64; 3]| {
+        let len = (n[0]*n[0] + n[1]*n[1] + n[2]*n[2]).sqrt();
+        [ Fixed::<Frac16>::from_f64(n[0] / len).to_bits(), Fixed::<Frac16>::from_f64(n[1] / len).to_bits(), Fixed::<Frac16>::from_f64(n[2] / len).to_bits() ]
+        };
+
+    let mut out = Vec::new();
+    {
+        let mut w = |v: u32| out.write_u32::<LittleEndian>(v).unwrap();
+        w(0x14);    // ID
+        w(1 << 23); // Scale (9.23)
+        w(0);
+        w(0);
+        w(vertices.len() as u32);
+        for v in &vertices {
+            w(v[0] as u32);
+            w(v[1] as u32);
+            w(v[2] as u32);
+        }
+
+        // Colour
+        w(0x0A);
+        w(0x0080_80FF);
+        // Sides
+        for i in 0 .. 4
+        {
+            let j = (i + 1) % 4;
+            let mid = [ (vertices[i][0] + vertices[j][0]) as f64, 0., (vertices[i][2] + vertices[j][2]) as f64 ];
+            let n = normal([ mid[0], h as f64, mid[2] ]);
+            w(0x0E);
+            w(3);
+            w(n[0] as u32);
+            w(n[1] as u32);
+            w(n[2] as u32);
+            w(0);   // Magic
+            for &(idx, u, v) in &[ (i, 0, 0), (j, 1, 0), (4, 0, 1) ]
+            {
+                w(idx as u32);
+                w(u);
+                w(v);
+            }
+        }
+        // Base
+        let n = normal([0., -1., 0.]);
+        w(0x0E);
+        w(4);
+        w(n[0] as u32);
+        w(n[1] as u32);
+        w(n[2] as u32);
+        w(0);
+        for &(idx, u, v) in &[ (3, 0, 0), (2, 1, 0), (1, 1, 1), (0, 0, 1) ]
+        {
+            w(idx);
+            w(u);
+            w(v);
+        }
+        w(0x00);
+    }
+    out
+}
+
+/// Heightmap (`.RAW` in DATA): rolling hills
+pub fn heightmap_raw(dim: usize) -> Vec<u8>
+{
+    let f = 2. * ::std::f64::consts::PI / dim as f64;
+    (0 .. dim*dim)
+        .map(|i| {
+            let (x, z) = ((i % dim) as f64, (i / dim) as f64);
+            (64. + 48. * (x * f * 2.).sin() * (z * f).cos()) as u8
+            })
+        .collect()
+}
+
+/// Texture map (`.CLR` in DATA): 8x8 cell blocks, cycling through the textures
+pub fn colour_map_clr(dim: usize, texture_count: usize) -> Vec<u8>
+{
+    (0 .. dim*dim)
+        .map(|i| ((i % dim / 8 + i / dim / 8) % texture_count) as u8)
+        .collect()
+}
+
+/// 256 entry palette (`.ACT`): a ramp from black to `tint`
+pub fn palette_act(tint: [u8; 3]) -> Vec<u8>
+{
+    (0 .. 256)
+        .flat_map(|i| {
+            let ch = move |c: u8| (c as usize * i / 255) as u8;
+            vec![ ch(tint[0]), ch(tint[1]), ch(tint[2]) ]
+            })
+        .collect()
+}
+
+/// Square paletted texture (`.RAW` in ART), a diagonal gradient over the 64 palette entries from `base`
+///
+/// Textures with different bases have different average colours, so they can be told apart in the level map.
+pub fn texture_raw(dim: usize, base: u8) -> Vec<u8>
+{
+    (0 .. dim*dim)
+        .map(|i| base.wrapping_add( ((i % dim + i / dim) * 63 / (2 * dim - 2)) as u8 ))
+        .collect()
+}
+
+/// Texture list (`.TEX` in DATA)
+pub fn texture_list_tex(names: &[String]) -> Vec<u8>
+{
+    let mut out = format!("{}\r\n", names.len());
+    for n in names {
+        out.push_str(n);
+        out.push_str("\r\n");
+    }
+    out.into_bytes()
+}
+
+/// Entity file (`.DEF` in DATA) with a grid of placements sitting on the `heightmap_raw` terrain
+///
+/// Type 0 is indestructible, and type 1 is destructible with a guaranteed drop.
+pub fn entities_def(dim: usize, model: &str, destroyed_model: &str) -> Vec<u8>
+{
+    let ty = |class: u8, hit_points: i64, drop: (u8, i8), description: &str| EntityType {
+        class: class,
+        unk_line1: [0; 5],
+        model: model.to_owned(),
+        model_destroyed: destroyed_model.to_owned(),
+        extra_line1: Vec::new(),
+        unk_line2: vec![hit_points, 0, 0, 0, 0],
+        drops: [ (0, 0), drop ],
+        extra_line3: Vec::new(),
+        unk_line4: vec![0; 4],
+        new_hit: vec![0; 4],
+        new_atak_ret: vec![0; 4],
+        description: description.to_owned(),
+        new_2nd_weapon: vec![0; 4],
+        sfx: [ "NONE".to_owned(), "NONE".to_owned() ],
+        };
+    let heights = heightmap_raw(dim);
+    let mut placements = Vec::new();
+    for gz in 1 .. 4
+    {
+        for gx in 1 .. 4
+        {
+            let (x, z) = (gx * dim / 4, gz * dim / 4);
+            // Entity coordinates are in cells offset by the map size, with Y in cells (32 height steps)
+            placements.push(EntityPlacement {
+                ty: (gx + gz) % 2,
+                flags: 0x1000,
+                x: Fixed::from_int(x as i32 - dim as i32),
+                y: Fixed::from_f64(heights[z * dim + x] as f64 / 32.),
+                z: Fixed::from_int(z as i32 - dim as i32),
+                unk1: 0,
+                unk2: 0,
+                unk3: 0,
+                extra: Vec::new(),
+                });
+        }
+    }
+    let types = vec![
+        ty(1, 0, (0, 0), "SYNTHETIC MARKER"),
+        ty(2, 100, (100, 1), "SYNTHETIC TARGET"),
+        ];
+    let file = EntityFile::new(types, placements);
+    let mut out = Vec::new();
+    file.write(&mut out).unwrap();
+    out
+}
+
+/// Level descriptor (`.LVL` in LEVELS), referencing the files added by `level_archives`
+pub fn level_lvl(level: &str) -> Vec<u8>
+{
+    let lines = [
+        "0".to_owned(),
+        "LEVEL.TXT".to_owned(),
+        format!("{}.RAW", level),
+        format!("{}.CLR", level),
+        format!("{}.ACT", level),
+        format!("{}.TEX", level),
+        format!("{}.QKE", level),
+        format!("{}.PUP", level),
+        format!("{}.ANI", level),
+        format!("{}.TUN", level),
+        format!("{}SKY.RAW", level),
+        format!("{}SKY.ACT", level),
+        format!("{}.DEF", level),
+        format!("{}.NAV", level),
+        format!("{}.MOD", level),
+        format!("{}.FOG", level),
+        format!("{}.LTE", level),
+        ];
+    let mut out = String::new();
+    for l in &lines {
+        out.push_str(l);
+        out.push_str("\r\n");
+    }
+    out.into_bytes()
+}
+
+/// Generate the `STARTUP.POD` and `FURY3.POD` archives for a complete synthetic level
+///
+/// `models` are extra model names to include (all the same pyramid), for any the game loads by name.
+pub fn level_archives(level: &str, models: &[&str]) -> (PodBuilder, PodBuilder)
+{
+    let mut startup = PodBuilder::new("Synthetic STARTUP.POD");
+    startup.add(r"STARTUP\README.TXT", b"Synthetic data, see src/datafile/synth.rs\r\n".to_vec());
+
+    let mut game = PodBuilder::new("Synthetic FURY3.POD");
+    let entity_model = "SYNPYR.BIN";
+    let destroyed_model = "SYNPYRD.BIN";
+    game.add(&format!(r"MODELS\{}", entity_model), model_bin(50));
+    game.add(&format!(r"MODELS\{}", destroyed_model), model_bin(20));
+    for m in models {
+        game.add(&format!(r"MODELS\{}", m), model_bin(50));
+    }
+
+    let texture_names: Vec<_> = (0 .. TEXTURE_COUNT).map(|i| format!("{}{}.RAW", level, i)).collect();
+    for (i, name) in texture_names.iter().enumerate()
+    {
+        game.add(&format!(r"ART\{}", name), texture_raw(TEXTURE_DIM >> (i % 2), i as u8 * 0x40));
+    }
+    // Only the first texture has its own palette, the rest use the level default
+    game.add(&format!(r"ART\{}0.ACT", level), palette_act([255, 128, 64]));
+    game.add(&format!(r"ART\{}.ACT", level), palette_act([128, 255, 128]));
+    game.add(&format!(r"ART\{}SKY.RAW", level), texture_raw(64, 0xC0));
+    game.add(&format!(r"ART\{}SKY.ACT", level), palette_act([128, 160, 255]));
+
+    game.add(&format!(r"DATA\{}.RAW", level), heightmap_raw(MAP_DIM));
+    game.add(&format!(r"DATA\{}.CLR", level), colour_map_clr(MAP_DIM, TEXTURE_COUNT));
+    game.add(&format!(r"DATA\{}.TEX", level), texture_list_tex(&texture_names));
+    game.add(&format!(r"DATA\{}.DEF", level), entities_def(MAP_DIM, entity_model, destroyed_model));
+    game.add(&format!(r"LEVELS\{}.LVL", level), level_lvl(level));
+
+    (startup, game)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use super::super::{PodArchive, IntegrityProblem, Level, Model};
+
+    #[test]
+    fn archive_round_trip()
+    {
+        let mut pod = PodBuilder::new("Test");
+        pod.add(r"data\a.txt", b"first".to_vec());
+        pod.add(r"ART\EMPTY.RAW", Vec::new());
+        pod.add(r"DATA\B.BIN", (0 .. 255).collect());
+        let archive = pod.to_archive().unwrap();
+        // The empty file is valid, but reported
+        let problems = archive.check().problems;
+        assert_eq!(problems.len(), 1);
+        match problems[0]
+        {
+        IntegrityProblem::ZeroLength { ref name } => assert_eq!(name, r"ART\EMPTY.RAW"),
+        ref p => panic!("Unexpected problem {:?}", p),
+        }
+        // Names are upper case, and listed in order
+        assert_eq!(archive.file_names(), vec![r"ART\EMPTY.RAW".to_owned(), r"DATA\A.TXT".to_owned(), r"DATA\B.BIN".to_owned()]);
+        assert_eq!(&archive.file_data(r"DATA\A.TXT").unwrap()[..], b"first");
+        assert_eq!(archive.file_data(r"ART\EMPTY.RAW").unwrap().len(), 0);
+        assert_eq!(&archive.dir_file_data("DATA", "B.BIN").unwrap()[..], &(0 .. 255).collect::<Vec<u8>>()[..]);
+
+        // Written out, the same bytes open as the same archive
+        let mut data = Vec::new();
+        pod.write(&mut data).unwrap();
+        assert_eq!(PodArchive::from_bytes(data).unwrap().file_names(), archive.file_names());
+
+        // Names have to fit (with a NUL) in 32 bytes
+        pod.add(&format!(r"DATA\{}.RAW", "X".repeat(32)), Vec::new());
+        assert_eq!(pod.write(Vec::new()).unwrap_err().kind(), ::std::io::ErrorKind::InvalidInput);
+    }
+
+    /// Every file of the synthetic level is accepted by its parser, and is consistent with the others
+    #[test]
+    fn level_files_parse()
+    {
+        let (startup, game) = level_archives("TEST", &["EXTRA.BIN"]);
+        let (startup, game) = (startup.to_archive().unwrap(), game.to_archive().unwrap());
+        assert!(startup.check().problems.is_empty());
+        assert!(game.check().problems.is_empty());
+
+        let file = |dir: &str, name: &str| game.dir_file_data(dir, name).unwrap().into_owned();
+        let level = Level::from_file( &file("LEVELS", "TEST.LVL")[..] ).unwrap();
+
+        assert_eq!(file("DATA", &level.heightmap).len(), MAP_DIM * MAP_DIM);
+        let clr = file("DATA", &level.texture_map);
+        assert_eq!(clr.len(), MAP_DIM * MAP_DIM);
+        assert!(clr.iter().all(|&v| (v as usize) < TEXTURE_COUNT));
+        assert_eq!(file("ART", &level.palette).len(), 256 * 3);
+        assert_eq!(file("ART", &level.sky_palette).len(), 256 * 3);
+        assert_eq!(file("ART", &level.sky_texture).len(), 64 * 64);
+
+        let tex_list = String::from_utf8(file("DATA", &level.texture_list)).unwrap();
+        let names: Vec<_> = tex_list.split("\r\n").skip(1).filter(|n| !n.is_empty()).collect();
+        assert_eq!(names.len(), TEXTURE_COUNT);
+        for (i, name) in names.iter().enumerate() {
+            let dim = TEXTURE_DIM >> (i % 2);
+            assert_eq!(file("ART", name).len(), dim * dim);
+        }
+
+        let entities = EntityFile::from_file( &file("DATA", &level.entities)[..] ).unwrap();
+        assert_eq!(entities.types.len(), 2);
+        assert_eq!(entities.placements.len(), 9);
+        for t in &entities.types
+        {
+            for name in &[&t.model, &t.model_destroyed]
+            {
+                let m = Model::from_bin_file( &file("MODELS", name)[..] ).unwrap();
+                assert_eq!(m.vertices.len(), 5);
+            }
+        }
+        Model::from_bin_file( &file("MODELS", "EXTRA.BIN")[..] ).unwrap();
+    }
+}
@@ -0,0 +1,214 @@
+//!
+//! Gameplay state components for level entities (placed from the `.DEF` file)
+//!
+use amethyst::ecs;
+use amethyst::ecs::Join;
+use amethyst::core::transform::Transform;
+use amethyst::renderer as a_renderer;
+
+use controls::Paused;
+use player::PlayerShip;
+
+/// Distance (render units) within which the player's ship runs into an entity
+const COLLISION_RADIUS: f32 = 0.25;
+/// Damage done to an entity each time the player's ship runs into it
+pub const RAM_DAMAGE: i32 = 50;
+
+/// Index of the entity's type (in the level's `.DEF` file)
+pub struct EntityType(pub usize);
+impl ecs::Component for EntityType
+{
+    type Storage = ecs::VecStorage<Self>;
+}
+
+/// Raw placement flags from the `.DEF` file (meaning not yet known, usually a multiple of 2^12)
+pub struct PlacementFlags(pub u16);
+impl ecs::Component for PlacementFlags
+{
+    type Storage = ecs::VecStorage<Self>;
+}
+
+pub struct Health
+{
+    pub current: i32,
+}
+impl Health
+{
+    pub fn new(hit_points: i32) -> Health {
+        Health { current: hit_points }
+    }
+    pub fn damage(&mut self, amount: i32) {
+        self.current -= amount;
+    }
+    pub fn is_dead(&self) -> bool {
+        self.current <= 0
+    }
+}
+impl ecs::Component for Health
+{
+    type Storage = ecs::VecStorage<Self>;
+}
+
+/// An entity with a separate model for when it has been destroyed
+pub struct Destructible
+{
+    pub destroyed_mesh: a_renderer::MeshHandle,
+    pub destroyed: bool,
+}
+impl ecs::Component for Destructible
+{
+    type Storage = ecs::DenseVecStorage<Self>;
+}
+
+/// Marks an entity that the player's ship is touching (so each collision only does damage once)
+#[derive(Default)]
+pub struct Touching;
+impl ecs::Component for Touching
+{
+    type Storage = ecs::NullStorage<Self>;
+}
+
+/// Register all of the above components
+pub fn register(world: &mut ::amethyst::prelude::World)
+{
+    world.register::<EntityType>();
+    world.register::<PlacementFlags>();
+    world.register::<Health>();
+    world.register::<Destructible>();
+    world.register::<Touching>();
+}
+
+/// Damages entities that the player's ship runs into
+pub struct CollisionSystem;
+impl<'s> ecs::System<'s> for CollisionSystem
+{
+    type SystemData = (
+        ecs::Entities<'s>,
+        ecs::Fetch<'s, Paused>,
+        ecs::ReadStorage<'s, PlayerShip>,
+        ecs::ReadStorage<'s, Transform>,
+        ecs::WriteStorage<'s, Health>,
+        ecs::WriteStorage<'s, Touching>,
+        );
+    fn run(&mut self, (entities, paused, ships, transforms, mut health, mut touching): Self::SystemData)
+    {
+        if paused.0 {
+            return ;
+        }
+        let ship_positions: Vec<_> = ships.join().map(|s| s.state.position).collect();
+        for (ent, h, t) in (&*entities, &mut health, &transforms).join()
+        {
+            let hit = ship_positions.iter().any(|p| {
+                let d = [t.0.w.x - p[0], t.0.w.y - p[1], t.0.w.z - p[2]];
+                d[0]*d[0] + d[1]*d[1] + d[2]*d[2] < COLLISION_RADIUS * COLLISION_RADIUS
+                });
+            if !hit {
+                touching.remove(ent);
+            }
+            else if touching.get(ent).is_none() {
+                touching.insert(ent, Touching);
+                if !h.is_dead() {
+                    h.damage(RAM_DAMAGE);
+                    debug!("Rammed {:?}, {} hit points left", ent, h.current);
+                }
+            }
+        }
+    }
+}
+
+/// Swaps the mesh of destructible entities once their health runs out
+pub struct DestructionSystem;
+impl<'s> ecs::System<'s> for DestructionSystem
+{
+    type SystemData = (
+        ecs::Fetch<'s, Paused>,
+        ecs::ReadStorage<'s, Health>,
+        ecs::WriteStorage<'s, Destructible>,
+        ecs::WriteStorage<'s, a_renderer::MeshHandle>,
+        );
+    fn run(&mut self, (paused, health, mut destructible, mut meshes): Self::SystemData)
+    {
+        if paused.0 {
+            return ;
+        }
+        for (h, d, mesh) in (&health, &mut destructible, &mut meshes).join()
+        {
+            if !d.destroyed && h.is_dead()
+            {
+                *mesh = d.destroyed_mesh.clone();
+                d.destroyed = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use amethyst::ecs::RunNow;
+    use amethyst::core::cgmath::Matrix4;
+    use amethyst::prelude::World;
+    use flight::ShipState;
+
+    fn world() -> World
+    {
+        let mut world = World::new();
+        world.register::<Transform>();
+        world.register::<a_renderer::MeshHandle>();
+        world.register::<PlayerShip>();
+        world.add_resource(Paused::default());
+        register(&mut world);
+        world
+    }
+    fn step(world: &mut World)
+    {
+        CollisionSystem.run_now(&world.res);
+        DestructionSystem.run_now(&world.res);
+        world.maintain();
+    }
+    fn move_ship(world: &mut World, ship: ecs::Entity, pos: [f32; 3])
+    {
+        world.write::<PlayerShip>().get_mut(ship).unwrap().state.position = pos;
+    }
+
+    /// Running into a destructible entity damages it once per collision, and swaps its mesh once it's destroyed
+    #[test]
+    fn ramming_destroys()
+    {
+        let mut world = world();
+        let storage = ::amethyst::assets::AssetStorage::<a_renderer::Mesh>::new();
+        let (model_a, model_b) = (storage.allocate(), storage.allocate());
+        let pos = [1., 2., 3.];
+        let target = world.create_entity()
+            .with(Transform(Matrix4::from_translation(pos.into())))
+            .with(model_a.clone())
+            .with(Health::new(RAM_DAMAGE * 2))
+            .with(Destructible { destroyed_mesh: model_b.clone(), destroyed: false })
+            .build();
+        let ship = world.create_entity()
+            .with(PlayerShip::new(ShipState::new([pos[0] + 1., pos[1], pos[2]], 0.)))
+            .build();
+        let mesh_id = |world: &World| world.read::<a_renderer::MeshHandle>().get(target).unwrap().id();
+
+        // Out of range
+        step(&mut world);
+        assert_eq!(world.read::<Health>().get(target).unwrap().current, RAM_DAMAGE * 2);
+
+        // Staying in contact only counts once
+        move_ship(&mut world, ship, pos);
+        step(&mut world);
+        step(&mut world);
+        assert_eq!(world.read::<Health>().get(target).unwrap().current, RAM_DAMAGE);
+        assert_eq!(mesh_id(&world), model_a.id());
+
+        // Second hit destroys it
+        move_ship(&mut world, ship, [pos[0] + 1., pos[1], pos[2]]);
+        step(&mut world);
+        move_ship(&mut world, ship, pos);
+        step(&mut world);
+        assert!(world.read::<Health>().get(target).unwrap().is_dead());
+        assert!(world.read::<Destructible>().get(target).unwrap().destroyed);
+        assert_eq!(mesh_id(&world), model_b.id());
+    }
+}
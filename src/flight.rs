@@ -0,0 +1,295 @@
+//!
+//! Player ship flight model (arcade-style, Fury3-like)
+//!
+//! `step` is a pure function of the previous state and the controls, and is always run with a fixed timestep
+//! (`FIXED_DT`) so results don't depend on the frame rate.
+use std::f32::consts::PI;
+
+/// Simulation timestep (seconds)
+pub const FIXED_DT: f32 = 1. / 60.;
+
+/// Pilot inputs, each axis from -1 to 1
+#[derive(Copy,Clone,Debug,Default)]
+pub struct FlightControls
+{
+    /// Change in throttle setting (positive to speed up)
+    pub throttle: f32,
+    /// Nose up (positive) or down
+    pub pitch: f32,
+    /// Turn left (positive) or right
+    pub yaw: f32,
+    /// Roll left (positive) or right, overrides the automatic banking
+    pub roll: f32,
+}
+
+/// Handling characteristics (speeds in render units per second, rates in radians per second)
+#[derive(Copy,Clone,Debug)]
+pub struct FlightParams
+{
+    pub min_speed: f32,
+    pub max_speed: f32,
+    /// Rate at which the speed approaches the throttle setting
+    pub acceleration: f32,
+    /// Throttle change per second at full input
+    pub throttle_rate: f32,
+    pub pitch_rate: f32,
+    pub yaw_rate: f32,
+    pub roll_rate: f32,
+    /// Bank angle used when turning without roll input
+    pub max_auto_bank: f32,
+    /// Extra turn rate at 90 degrees of bank
+    pub bank_turn_rate: f32,
+    /// Pitch limit (either direction)
+    pub max_pitch: f32,
+    /// Minimum height above the terrain
+    pub ground_clearance: f32,
+}
+impl Default for FlightParams
+{
+    fn default() -> FlightParams
+    {
+        FlightParams {
+            min_speed: 0.5,
+            max_speed: 4.0,
+            acceleration: 2.0,
+            throttle_rate: 0.75,
+            pitch_rate: 1.2,
+            yaw_rate: 1.0,
+            roll_rate: 3.0,
+            max_auto_bank: 0.6,
+            bank_turn_rate: 0.8,
+            max_pitch: 80f32.to_radians(),
+            ground_clearance: 0.1,
+        }
+    }
+}
+
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub struct ShipState
+{
+    pub position: [f32; 3],
+    /// Heading (radians, rotation about Y, zero facing -Z)
+    pub yaw: f32,
+    /// Nose angle above the horizon (radians)
+    pub pitch: f32,
+    /// Bank angle (radians, positive is left wing down)
+    pub bank: f32,
+    /// Throttle setting (0 to 1)
+    pub throttle: f32,
+    pub speed: f32,
+    /// Set if the ship hit the terrain during the last step
+    pub ground_contact: bool,
+}
+impl ShipState
+{
+    pub fn new(position: [f32; 3], yaw: f32) -> ShipState
+    {
+        ShipState {
+            position: position,
+            yaw: yaw,
+            pitch: 0.,
+            bank: 0.,
+            throttle: 0.,
+            speed: 0.,
+            ground_contact: false,
+        }
+    }
+
+    /// Unit vector in the direction of travel
+    pub fn forward(&self) -> [f32; 3]
+    {
+        [
+            -self.yaw.sin() * self.pitch.cos(),
+            self.pitch.sin(),
+            -self.yaw.cos() * self.pitch.cos(),
+            ]
+    }
+
+    /// Render-space transform (rotation then translation)
+    pub fn matrix(&self) -> ::amethyst::core::cgmath::Matrix4<f32>
+    {
+        use amethyst::core::cgmath::{Matrix4, Rad};
+        Matrix4::from_translation(self.position.into())
+            * Matrix4::from_angle_y(Rad(self.yaw))
+            * Matrix4::from_angle_x(Rad(self.pitch))
+            * Matrix4::from_angle_z(Rad(self.bank))
+    }
+}
+
+/// Interpolate between two states (for rendering between simulation steps), `t` from 0 (`a`) to 1 (`b`)
+pub fn lerp(a: &ShipState, b: &ShipState, t: f32) -> ShipState
+{
+    let l = |a: f32, b: f32| a + (b - a) * t;
+    // Angles take the short way around
+    let la = |a: f32, b: f32| {
+        let mut d = b - a;
+        if d > PI { d -= 2. * PI; }
+        if d < -PI { d += 2. * PI; }
+        a + d * t
+        };
+    ShipState {
+        position: [ l(a.position[0], b.position[0]), l(a.position[1], b.position[1]), l(a.position[2], b.position[2]) ],
+        yaw: la(a.yaw, b.yaw),
+        pitch: l(a.pitch, b.pitch),
+        bank: la(a.bank, b.bank),
+        throttle: l(a.throttle, b.throttle),
+        speed: l(a.speed, b.speed),
+        ground_contact: b.ground_contact,
+    }
+}
+
+fn clamp(v: f32, min: f32, max: f32) -> f32 {
+    v.max(min).min(max)
+}
+/// Move `v` towards `target` by at most `step`
+fn approach(v: f32, target: f32, step: f32) -> f32 {
+    if v < target { (v + step).min(target) } else { (v - step).max(target) }
+}
+
+/// Advance the ship by `FIXED_DT`
+///
+/// `ground_height` returns the terrain height below a position.
+pub fn step<G>(state: &ShipState, controls: &FlightControls, params: &FlightParams, ground_height: G) -> ShipState
+where
+    G: Fn([f32; 3]) -> f32
+{
+    let dt = FIXED_DT;
+    let mut s = *state;
+
+    // Throttle and speed
+    s.throttle = clamp(s.throttle + clamp(controls.throttle, -1., 1.) * params.throttle_rate * dt, 0., 1.);
+    let target_speed = params.min_speed + (params.max_speed - params.min_speed) * s.throttle;
+    s.speed = approach(s.speed, target_speed, params.acceleration * dt);
+
+    // Banking: free roll with roll input, otherwise bank into the turn
+    if controls.roll != 0. {
+        s.bank += clamp(controls.roll, -1., 1.) * params.roll_rate * dt;
+        if s.bank > PI { s.bank -= 2. * PI; }
+        if s.bank < -PI { s.bank += 2. * PI; }
+    }
+    else {
+        let target_bank = clamp(controls.yaw, -1., 1.) * params.max_auto_bank;
+        s.bank = approach(s.bank, target_bank, params.roll_rate * dt);
+    }
+
+    // Rotation: direct yaw, plus a turn from the bank angle
+    s.yaw += (clamp(controls.yaw, -1., 1.) * params.yaw_rate + s.bank.sin() * params.bank_turn_rate) * dt;
+    if s.yaw > PI { s.yaw -= 2. * PI; }
+    if s.yaw < -PI { s.yaw += 2. * PI; }
+    s.pitch = clamp(s.pitch + clamp(controls.pitch, -1., 1.) * params.pitch_rate * dt, -params.max_pitch, params.max_pitch);
+
+    // Movement
+    let fwd = s.forward();
+    for i in 0 .. 3 {
+        s.position[i] += fwd[i] * s.speed * dt;
+    }
+
+    // Terrain collision: stay above the ground, and level out if diving into it
+    let floor = ground_height(s.position) + params.ground_clearance;
+    s.ground_contact = s.position[1] < floor;
+    if s.ground_contact {
+        s.position[1] = floor;
+        s.pitch = s.pitch.max(0.);
+    }
+
+    s
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    const EPS: f32 = 1e-4;
+
+    fn run(state: ShipState, controls: FlightControls, steps: usize, ground: f32) -> ShipState
+    {
+        let params = FlightParams::default();
+        (0 .. steps).fold(state, |s, _| step(&s, &controls, &params, |_| ground))
+    }
+    fn controls(throttle: f32, pitch: f32, yaw: f32, roll: f32) -> FlightControls
+    {
+        FlightControls { throttle: throttle, pitch: pitch, yaw: yaw, roll: roll }
+    }
+    fn start() -> ShipState
+    {
+        ShipState::new([0., 10., 0.], 0.)
+    }
+
+    #[test]
+    fn thrust()
+    {
+        let p = FlightParams::default();
+        // One second of full throttle
+        let s = run(start(), controls(1., 0., 0., 0.), 60, 0.);
+        assert!((s.throttle - p.throttle_rate).abs() < EPS, "{:?}", s);
+        assert!(s.speed > p.min_speed && s.speed <= p.min_speed + (p.max_speed - p.min_speed) * s.throttle, "{:?}", s);
+        // Moving forwards (-Z) at the current speed
+        let s2 = run(s, controls(0., 0., 0., 0.), 1, 0.);
+        assert!((s.position[2] - s2.position[2] - s2.speed * FIXED_DT).abs() < EPS, "{:?} {:?}", s, s2);
+
+        // Speed limits
+        let s = run(start(), controls(1., 0., 0., 0.), 60 * 10, 0.);
+        assert_eq!((s.throttle, s.speed), (1., p.max_speed));
+        let s = run(s, controls(-1., 0., 0., 0.), 60 * 10, 0.);
+        assert_eq!((s.throttle, s.speed), (0., p.min_speed));
+        // Inputs past full deflection are clamped
+        let a = run(start(), controls(1., 0., 0., 0.), 10, 0.);
+        let b = run(start(), controls(5., 0., 0., 0.), 10, 0.);
+        assert_eq!(a, b);
+    }
+
+    /// The arcade model has no gravity: level flight holds its height, and only pitch changes it
+    #[test]
+    fn gravity()
+    {
+        let s = run(start(), controls(1., 0., 0., 0.), 60 * 5, 0.);
+        assert_eq!(s.position[1], start().position[1]);
+        assert!(s.position[2] < -1., "{:?}", s);
+
+        let up = run(start(), controls(0., 1., 0., 0.), 60, 0.);
+        assert!(up.pitch > 0. && up.position[1] > start().position[1], "{:?}", up);
+        let down = run(start(), controls(0., -1., 0., 0.), 60, 0.);
+        assert!(down.pitch < 0. && down.position[1] < start().position[1], "{:?}", down);
+    }
+
+    #[test]
+    fn ground_clamp()
+    {
+        let p = FlightParams::default();
+        // Diving at the ground stops at the clearance height, and levels out
+        let s = run(start(), controls(1., -1., 0., 0.), 60 * 20, 9.);
+        assert_eq!(s.position[1], 9. + p.ground_clearance);
+        assert!(s.ground_contact);
+        assert!(s.pitch >= 0., "{:?}", s);
+
+        // A ship below the terrain is lifted onto it
+        let s = run(ShipState::new([0., 0., 0.], 0.), controls(0., 0., 0., 0.), 1, 3.);
+        assert_eq!(s.position[1], 3. + p.ground_clearance);
+        assert!(s.ground_contact);
+        // Contact clears once above the ground again
+        let s = run(s, controls(0., 0., 0., 0.), 1, 0.);
+        assert!(!s.ground_contact);
+    }
+
+    #[test]
+    fn limits()
+    {
+        let p = FlightParams::default();
+        // Pitch stops at the limit in both directions
+        let s = run(start(), controls(0., 1., 0., 0.), 60 * 5, -1000.);
+        assert_eq!(s.pitch, p.max_pitch);
+        let s = run(start(), controls(0., -1., 0., 0.), 60 * 5, -1000.);
+        assert_eq!(s.pitch, -p.max_pitch);
+
+        // Automatic banking stops at the limit, and levels out without input
+        let s = run(start(), controls(0., 0., 1., 0.), 60 * 5, 0.);
+        assert!((s.bank - p.max_auto_bank).abs() < EPS, "{:?}", s);
+        let s = run(s, controls(0., 0., 0., 0.), 60 * 5, 0.);
+        assert_eq!(s.bank, 0.);
+
+        // Angles stay wrapped to (-PI, PI]
+        let s = run(start(), controls(0., 0., 1., 1.), 60 * 20, 0.);
+        assert!(s.yaw.abs() <= PI && s.bank.abs() <= PI, "{:?}", s);
+    }
+}
@@ -0,0 +1,133 @@
+//!
+//! Change detection for the loose file override directory (`FURY3_OVERRIDE`)
+//!
+//! The directory is polled (comparing modification times), and `GameRoot` reloads whatever changed.
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use super::DataFolder;
+
+/// Time between scans of the override directory
+const SCAN_INTERVAL_MS: u64 = 500;
+/// Folders containing reloadable files
+const FOLDERS: [DataFolder; 3] = [DataFolder::Art, DataFolder::Data, DataFolder::Models];
+
+pub(crate) struct OverrideWatcher
+{
+    dir: PathBuf,
+    /// Folder and last seen modification time of each file
+    mtimes: HashMap<PathBuf, (DataFolder, SystemTime)>,
+    last_scan: Instant,
+}
+impl OverrideWatcher
+{
+    pub(crate) fn new(dir: &Path) -> OverrideWatcher
+    {
+        let mut rv = OverrideWatcher {
+            dir: dir.to_owned(),
+            mtimes: HashMap::new(),
+            last_scan: Instant::now(),
+            };
+        // Files that are already there were used by the initial load
+        let existing = rv.scan();
+        debug!("Watching {} for changes ({} files)", dir.display(), existing.len());
+        rv
+    }
+
+    /// Files added, modified or removed since the last call, as their folder and (upper case) name
+    ///
+    /// A removed file is reloaded the same way as a changed one, which then reads it from the archives again.
+    pub(crate) fn poll(&mut self) -> Vec<(DataFolder, String)>
+    {
+        if self.last_scan.elapsed() < Duration::from_millis(SCAN_INTERVAL_MS) {
+            return Vec::new();
+        }
+        self.last_scan = Instant::now();
+        self.scan()
+    }
+
+    fn scan(&mut self) -> Vec<(DataFolder, String)>
+    {
+        let mut rv = Vec::new();
+        let mut seen = HashSet::new();
+        for &folder in &FOLDERS
+        {
+            let entries = match ::std::fs::read_dir(self.dir.join(folder.dir_name()))
+                {
+                Ok(v) => v,
+                Err(_) => continue,
+                };
+            for ent in entries.filter_map(|e| e.ok())
+            {
+                let mtime = match ent.metadata().and_then(|m| m.modified())
+                    {
+                    Ok(v) => v,
+                    Err(_) => continue,
+                    };
+                let path = ent.path();
+                seen.insert(path.clone());
+                if self.mtimes.insert(path.clone(), (folder, mtime)) == Some( (folder, mtime) ) {
+                    continue;
+                }
+                if let Some(name) = path.file_name().and_then(|v| v.to_str()) {
+                    rv.push( (folder, name.to_ascii_uppercase()) );
+                }
+            }
+        }
+        let removed: Vec<_> = self.mtimes.keys().filter(|p| !seen.contains(*p)).cloned().collect();
+        for path in removed
+        {
+            let (folder, _) = self.mtimes.remove(&path).unwrap();
+            if let Some(name) = path.file_name().and_then(|v| v.to_str()) {
+                rv.push( (folder, name.to_ascii_uppercase()) );
+            }
+        }
+        rv
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// Rewrite a file until its modification time changes (as the filesystem's resolution may be coarse)
+    fn touch(path: &Path)
+    {
+        let before = ::std::fs::metadata(path).unwrap().modified().unwrap();
+        for _ in 0 .. 300
+        {
+            ::std::thread::sleep(Duration::from_millis(10));
+            ::std::fs::write(path, b"changed").unwrap();
+            if ::std::fs::metadata(path).unwrap().modified().unwrap() != before {
+                return;
+            }
+        }
+        panic!("Modification time of {} didn't change", path.display());
+    }
+
+    #[test]
+    fn added_changed_removed()
+    {
+        let dir = ::std::env::temp_dir().join(format!("fury3clone-watch-{}", ::std::process::id()));
+        ::std::fs::create_dir_all(dir.join("MODELS")).unwrap();
+        ::std::fs::write(dir.join("MODELS/OLD.BIN"), b"old").unwrap();
+        let mut watcher = OverrideWatcher::new(&dir);
+        assert_eq!(watcher.scan(), vec![]);
+
+        let path = dir.join("MODELS/tent2.bin");
+        ::std::fs::write(&path, b"new").unwrap();
+        assert_eq!(watcher.scan(), vec![ (DataFolder::Models, "TENT2.BIN".to_owned()) ]);
+        assert_eq!(watcher.scan(), vec![]);
+
+        touch(&path);
+        assert_eq!(watcher.scan(), vec![ (DataFolder::Models, "TENT2.BIN".to_owned()) ]);
+        assert_eq!(watcher.scan(), vec![]);
+
+        ::std::fs::remove_file(&path).unwrap();
+        assert_eq!(watcher.scan(), vec![ (DataFolder::Models, "TENT2.BIN".to_owned()) ]);
+        assert_eq!(watcher.scan(), vec![]);
+        ::std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,270 @@
+//!
+//! Top-down level map renderer (`fury3clone map <LEVEL.LVL> <output.png>`)
+//!
+//! Draws the heightmap shaded by height and coloured by each cell's texture, with every placed entity marked and
+//! labelled with its description. Entities are placed using the same conversion as the 3D view, so this shows how well
+//! the entity coordinate scaling lines up with the terrain.
+use terrain;
+use world_units::WorldUnits;
+use super::{BoxError, DataPath, GameRoot, PodFiles, PodName, DataFolder};
+
+/// Output pixels per map cell
+const CELL_PIXELS: usize = 4;
+
+pub fn render_to_file(level_name: &str, out_path: &str) -> Result<(), BoxError>
+{
+    let mut root = GameRoot::new(PodFiles::open(super::system_dir())?, true);
+    let image = render(&mut root, level_name)?;
+
+    let fp = ::std::io::BufWriter::new( ::std::fs::File::create(out_path)? );
+    let mut enc = ::png::Encoder::new(fp, image.width as u32, image.height as u32);
+    enc.set(::png::ColorType::RGB).set(::png::BitDepth::Eight);
+    enc.write_header()?.write_image_data(&image.data)?;
+    Ok( () )
+}
+
+struct Image
+{
+    width: usize,
+    height: usize,
+    /// RGB, row-major
+    data: Vec<u8>,
+}
+impl Image
+{
+    fn put(&mut self, x: isize, y: isize, c: [u8; 3])
+    {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return ;
+        }
+        let ofs = (y as usize * self.width + x as usize) * 3;
+        self.data[ofs .. ofs + 3].copy_from_slice(&c);
+    }
+
+    /// Draw text using the `glyph` font, with a dark outline so it's readable over the terrain
+    fn text(&mut self, x: isize, y: isize, s: &str, c: [u8; 3])
+    {
+        for &(dx, dy, c) in &[ (-1,0,[0;3]), (1,0,[0;3]), (0,-1,[0;3]), (0,1,[0;3]), (0,0,c) ]
+        {
+            for (i, ch) in s.chars().enumerate()
+            {
+                let glyph = match glyph(ch)
+                    {
+                    Some(v) => v,
+                    None => continue,
+                    };
+                for (row, bits) in glyph.iter().enumerate()
+                {
+                    for col in 0 .. 3
+                    {
+                        if bits & (4 >> col) != 0 {
+                            self.put(x + dx + (i * 4 + col) as isize, y + dy + row as isize, c);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn render(root: &mut GameRoot, level_name: &str) -> Result<Image, BoxError>
+{
+    let level = root.load_level_file(level_name)?;
+    let mut image = render_terrain(root, &level)?;
+    let (entity_types, entity_list) = root.load_entities_file(datapath!(Game, Data, &level.entities))?;
+    let units = WorldUnits::new(image.width / CELL_PIXELS + 1);
+
+    // Entities: a cross coloured by type, and the type's description
+    for e in &entity_list
+    {
+        let (mx, mz) = units.terrain_cell(e.render_position(&units));
+        let (px, py) = ((mx * CELL_PIXELS as f32) as isize, (mz * CELL_PIXELS as f32) as isize);
+        let c = type_colour(e.ty);
+        for d in -2 .. 3
+        {
+            image.put(px + d, py, c);
+            image.put(px, py + d, c);
+        }
+        let desc = entity_types.get(e.ty).map(|t| &t.description[..]).unwrap_or("?");
+        image.text(px + 4, py - 2, &desc.to_uppercase(), [255; 3]);
+    }
+
+    Ok(image)
+}
+
+/// Terrain: texture colour, shaded by the average height of the cell
+fn render_terrain(root: &mut GameRoot, level: &::datafile::Level) -> Result<Image, BoxError>
+{
+    let heights = terrain::ByteGrid::from_reader( root.pods.open_file(datapath!(Game, Data, &level.heightmap))? )?;
+    let colours = terrain::ByteGrid::from_reader( root.pods.open_file(datapath!(Game, Data, &level.texture_map))? )?;
+    let tex_colours = average_texture_colours(root, level)?;
+
+    let dim = heights.dim();
+    let mut image = Image {
+        width: (dim - 1) * CELL_PIXELS,
+        height: (dim - 1) * CELL_PIXELS,
+        data: vec![0; (dim - 1) * (dim - 1) * CELL_PIXELS * CELL_PIXELS * 3],
+        };
+
+    for z in 0 .. dim - 1
+    {
+        for x in 0 .. dim - 1
+        {
+            let (x_i, z_i) = (x as isize, z as isize);
+            let h = (heights.get(x_i, z_i) as u32 + heights.get(x_i+1, z_i) as u32
+                + heights.get(x_i, z_i+1) as u32 + heights.get(x_i+1, z_i+1) as u32) / 4;
+            let tex_id = terrain::cell_texture_index(&colours, x, z);
+            let c = shade(tex_colours.get(tex_id).cloned().unwrap_or([255, 0, 255]), h);
+            for py in 0 .. CELL_PIXELS
+            {
+                for px in 0 .. CELL_PIXELS
+                {
+                    image.put((x * CELL_PIXELS + px) as isize, (z * CELL_PIXELS + py) as isize, c);
+                }
+            }
+        }
+    }
+    Ok(image)
+}
+
+/// Darken a colour by height (0-255), so the terrain shape is visible
+fn shade(base: [u8; 3], height: u32) -> [u8; 3]
+{
+    let shade = 0.35 + 0.65 * height as f32 / 255.;
+    [
+        (base[0] as f32 * shade) as u8,
+        (base[1] as f32 * shade) as u8,
+        (base[2] as f32 * shade) as u8,
+        ]
+}
+
+/// Average colour of each texture in the level's texture list
+fn average_texture_colours(root: &mut GameRoot, level: &::datafile::Level) -> Result<Vec<[u8; 3]>, BoxError>
+{
+    use std::io::Read;
+
+    let default_plt = root.pods.load_palette(datapath!(Game, Art, &level.palette))?;
+    let mut rv = Vec::new();
+    for name in root.load_texture_list(datapath!(Game, Data, &level.texture_list))?
+    {
+        let act_fname = format!("{}ACT", &name[..name.len() - 3]);
+        let palette = root.pods.load_palette(datapath!(Game, Art, &act_fname)).unwrap_or_else(|_| default_plt.clone());
+
+        let mut pixels = Vec::new();
+        root.pods.open_file(datapath!(Game, Art, &name))?.read_to_end(&mut pixels)?;
+        let mut sum = [0u64; 3];
+        for &b in &pixels
+        {
+            for i in 0 .. 3 {
+                sum[i] += palette[b as usize * 3 + i] as u64;
+            }
+        }
+        let n = ::std::cmp::max(1, pixels.len() as u64);
+        rv.push([ (sum[0] / n) as u8, (sum[1] / n) as u8, (sum[2] / n) as u8 ]);
+    }
+    Ok(rv)
+}
+
+/// Distinct-ish marker colour for an entity type
+fn type_colour(ty: usize) -> [u8; 3]
+{
+    const COLOURS: [[u8; 3]; 8] = [
+        [255, 0, 0], [0, 255, 0], [0, 128, 255], [255, 255, 0],
+        [255, 0, 255], [0, 255, 255], [255, 128, 0], [255, 255, 255],
+        ];
+    COLOURS[ty % COLOURS.len()]
+}
+
+/// 3x5 font for labels, each row is three bits (MSB on the left)
+fn glyph(ch: char) -> Option<&'static [u8; 5]>
+{
+    static LETTERS: [[u8; 5]; 26] = [
+        [0b010,0b101,0b111,0b101,0b101], [0b110,0b101,0b110,0b101,0b110], [0b011,0b100,0b100,0b100,0b011],   // A B C
+        [0b110,0b101,0b101,0b101,0b110], [0b111,0b100,0b110,0b100,0b111], [0b111,0b100,0b110,0b100,0b100],   // D E F
+        [0b011,0b100,0b101,0b101,0b011], [0b101,0b101,0b111,0b101,0b101], [0b111,0b010,0b010,0b010,0b111],   // G H I
+        [0b001,0b001,0b001,0b101,0b010], [0b101,0b101,0b110,0b101,0b101], [0b100,0b100,0b100,0b100,0b111],   // J K L
+        [0b101,0b111,0b111,0b101,0b101], [0b110,0b101,0b101,0b101,0b101], [0b010,0b101,0b101,0b101,0b010],   // M N O
+        [0b110,0b101,0b110,0b100,0b100], [0b010,0b101,0b101,0b110,0b011], [0b110,0b101,0b110,0b101,0b101],   // P Q R
+        [0b011,0b100,0b010,0b001,0b110], [0b111,0b010,0b010,0b010,0b010], [0b101,0b101,0b101,0b101,0b111],   // S T U
+        [0b101,0b101,0b101,0b101,0b010], [0b101,0b101,0b111,0b111,0b101], [0b101,0b101,0b010,0b101,0b101],   // V W X
+        [0b101,0b101,0b010,0b010,0b010], [0b111,0b001,0b010,0b100,0b111],   // Y Z
+        ];
+    static DIGITS: [[u8; 5]; 10] = [
+        [0b111,0b101,0b101,0b101,0b111], [0b010,0b110,0b010,0b010,0b111], [0b110,0b001,0b010,0b100,0b111],   // 0 1 2
+        [0b110,0b001,0b010,0b001,0b110], [0b101,0b101,0b111,0b001,0b001], [0b111,0b100,0b110,0b001,0b110],   // 3 4 5
+        [0b011,0b100,0b111,0b101,0b111], [0b111,0b001,0b010,0b010,0b010], [0b111,0b101,0b111,0b101,0b111],   // 6 7 8
+        [0b111,0b101,0b111,0b001,0b110],    // 9
+        ];
+    static DASH: [u8; 5] = [0, 0, 0b111, 0, 0];
+    static DOT: [u8; 5] = [0, 0, 0, 0, 0b010];
+    match ch
+    {
+    'A' ..= 'Z' => Some(&LETTERS[ch as usize - 'A' as usize]),
+    '0' ..= '9' => Some(&DIGITS[ch as usize - '0' as usize]),
+    '-' => Some(&DASH),
+    '.' => Some(&DOT),
+    _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// The terrain layer of the synthetic level matches a reference rasterised straight from its `.CLR`, heightmap,
+    /// textures and palettes
+    #[test]
+    fn terrain_matches_clr_reference()
+    {
+        let mut root = GameRoot::new(PodFiles::synthetic(), true);
+        let level = root.load_level_file("EGYPT.LVL").unwrap();
+        let image = render_terrain(&mut root, &level).unwrap();
+
+        let file = |folder: DataFolder, name: &str| root.pods.file_data(DataPath { archive: PodName::Game, folder: folder, file: name }).unwrap().into_owned();
+        let heights = file(DataFolder::Data, &level.heightmap);
+        let clr = file(DataFolder::Data, &level.texture_map);
+        let tex_list = String::from_utf8(file(DataFolder::Data, &level.texture_list)).unwrap();
+        let default_palette = file(DataFolder::Art, &level.palette);
+        // Average colour of each listed texture, using its own palette if there is one
+        let tex_colours: Vec<[u8; 3]> = tex_list.split("\r\n").skip(1).filter(|n| !n.is_empty())
+            .map(|name| {
+                let pixels = file(DataFolder::Art, name);
+                let palette = root.pods.file_data(datapath!(Game, Art, &format!("{}ACT", &name[..name.len() - 3])))
+                    .map(|v| v.into_owned())
+                    .unwrap_or_else(|_| default_palette.clone());
+                let mut sum = [0u64; 3];
+                for &p in &pixels {
+                    for i in 0 .. 3 {
+                        sum[i] += palette[p as usize * 3 + i] as u64;
+                    }
+                }
+                let n = pixels.len() as u64;
+                [ (sum[0] / n) as u8, (sum[1] / n) as u8, (sum[2] / n) as u8 ]
+                })
+            .collect();
+
+        let dim = (heights.len() as f64).sqrt() as usize;
+        assert_eq!(image.width, (dim - 1) * CELL_PIXELS);
+        let mut seen = ::std::collections::HashSet::new();
+        for z in 0 .. dim - 1
+        {
+            for x in 0 .. dim - 1
+            {
+                // The cell with its top-left corner at heightmap pixel (x,z) is textured by `.CLR` pixel (x+1,z)
+                let tex_id = clr[z * dim + x + 1] as usize;
+                let h = (heights[z * dim + x] as u32 + heights[z * dim + x + 1] as u32
+                    + heights[(z + 1) * dim + x] as u32 + heights[(z + 1) * dim + x + 1] as u32) / 4;
+                let expected = shade(tex_colours[tex_id], h);
+                seen.insert(tex_colours[tex_id]);
+                for &(px, py) in &[ (0, 0), (CELL_PIXELS - 1, CELL_PIXELS - 1) ]
+                {
+                    let ofs = ((z * CELL_PIXELS + py) * image.width + x * CELL_PIXELS + px) * 3;
+                    assert_eq!(&image.data[ofs .. ofs + 3], &expected[..], "Cell {},{} (texture {})", x, z, tex_id);
+                }
+            }
+        }
+        // Every texture is used and distinguishable, so a shifted mapping would be caught
+        assert_eq!(seen.len(), tex_colours.len());
+    }
+}
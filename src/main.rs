@@ -4,30 +4,44 @@ extern crate byteorder;
 extern crate log;
 extern crate env_logger;
 extern crate gfx_core;
+extern crate png;
+extern crate memmap;
 
 use amethyst::prelude::*;
 use amethyst::renderer::Rgba;
 use amethyst::renderer::Event;
 use amethyst::core::transform::Transform;
-use amethyst::core::cgmath::Deg;
-use amethyst::core::cgmath::Vector3;
 use amethyst::ecs;
 use amethyst::core::cgmath::Matrix4;
 
 use amethyst::renderer as a_renderer;
 
 mod datafile;
+mod terrain;
+mod terrain_lod;
+mod world_units;
+mod entity_state;
+mod powerups;
+mod flight;
+mod player;
+mod camera;
+mod controls;
+#[cfg(test)]
+mod conformance;
+mod pod_assets;
+mod hot_reload;
+mod sky;
 
 type BoxError = Box<::std::error::Error>;
 
-#[derive(Copy,Clone,Debug)]
+#[derive(Copy,Clone,Debug,PartialEq,Eq,Hash)]
 #[allow(dead_code)]
 enum PodName
 {
     Startup,
     Game,
 }
-#[derive(Copy,Clone,Debug)]
+#[derive(Copy,Clone,Debug,PartialEq,Eq,Hash)]
 #[allow(dead_code)]
 enum DataFolder
 {
@@ -41,6 +55,25 @@ enum DataFolder
     Sound,
     Startup,
 }
+impl DataFolder
+{
+    /// Directory name within the archive
+    fn dir_name(&self) -> &'static str
+    {
+        match *self
+        {
+        DataFolder::Art => "ART",
+        DataFolder::Data => "DATA",
+        DataFolder::Demo => "DEMO",
+        DataFolder::Fog => "FOG",
+        DataFolder::Levels => "LEVELS",
+        DataFolder::Models => "MODELS",
+        DataFolder::Music => "MUSIC",
+        DataFolder::Sound => "SOUND",
+        DataFolder::Startup => "STARTUP",
+        }
+    }
+}
 #[derive(Copy,Clone,Debug)]
 struct DataPath<'a>
 {
@@ -49,18 +82,127 @@ struct DataPath<'a>
     file: &'a str,
 }
 macro_rules! datapath {
-    ($a:ident, $d:ident, $f:expr) => ( DataPath { archive: PodName::$a, folder: DataFolder::$d, file: $f, } );
+    ($a:ident, $d:ident, $f:expr) => ( $crate::DataPath { archive: $crate::PodName::$a, folder: $crate::DataFolder::$d, file: $f, } );
+}
+impl<'a> DataPath<'a>
+{
+    /// Owned copy, for use as a key (file names are case-insensitive, so are upper-cased)
+    fn to_buf(&self) -> DataPathBuf
+    {
+        DataPathBuf {
+            archive: self.archive,
+            folder: self.folder,
+            file: self.file.to_ascii_uppercase(),
+            }
+    }
+}
+/// Owned version of `DataPath`
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+struct DataPathBuf
+{
+    archive: PodName,
+    folder: DataFolder,
+    file: String,
+}
+impl DataPathBuf
+{
+    fn as_path(&self) -> DataPath
+    {
+        DataPath {
+            archive: self.archive,
+            folder: self.folder,
+            file: &self.file,
+            }
+    }
 }
 
+// Modules using `datapath!`
+mod level_map;
+mod asset_cache;
+
 
 struct GameRoot
 {
-    pods: PodFiles,
+    /// Shared with the loader threads
+    pods: ::std::sync::Arc<PodFiles>,
+    assets: asset_cache::AssetCache,
+    presses: controls::PressTracker,
+    /// Set when running without a renderer (no meshes, materials or lights are created)
+    headless: bool,
+    level_files: LevelFiles,
+    /// Entities created from the level's placements, in the same order
+    level_entities: Vec<ecs::Entity>,
+    /// Set when there's an override directory (and not headless)
+    watcher: Option<hot_reload::OverrideWatcher>,
+    sky: Option<ecs::Entity>,
+}
+/// Names of the current level's files (upper case), used to reload them
+#[derive(Clone,Default)]
+struct LevelFiles
+{
+    /// Heightmap in DATA (the colour map has the same name, with `.CLR`)
+    heightmap: String,
+    /// Texture list in DATA
+    texture_list: String,
+    /// Default texture palette in ART
+    palette: String,
+    /// Entity file in DATA
+    entities: String,
+    /// Sky texture in ART
+    sky_texture: String,
+    /// Sky palette in ART
+    sky_palette: String,
+}
+impl LevelFiles
+{
+    fn from_level(level: &datafile::Level) -> LevelFiles
+    {
+        LevelFiles {
+            heightmap: level.heightmap.to_ascii_uppercase(),
+            texture_list: level.texture_list.to_ascii_uppercase(),
+            palette: level.palette.to_ascii_uppercase(),
+            entities: level.entities.to_ascii_uppercase(),
+            sky_texture: level.sky_texture.to_ascii_uppercase(),
+            sky_palette: level.sky_palette.to_ascii_uppercase(),
+            }
+    }
+
+    fn colour_map(&self) -> String
+    {
+        format!("{}CLR", &self.heightmap[..self.heightmap.len() - 3])
+    }
+
+    /// Palette with the same name as the sky texture, used in place of `sky_palette` if it exists
+    fn sky_texture_palette(&self) -> String
+    {
+        format!("{}ACT", &self.sky_texture[..self.sky_texture.len() - 3])
+    }
 }
 struct PodFiles
 {
     startup: self::datafile::PodArchive,
     game: self::datafile::PodArchive,
+    /// Directory holding the archives (`None` if they're only in memory)
+    system_dir: Option<::std::path::PathBuf>,
+    /// Loose files layered over both archives (see `OVERRIDE_DIR_VAR`)
+    overrides: Option<::std::path::PathBuf>,
+}
+/// A file opened from the archives or the override directory
+enum DataFile<'a>
+{
+    Pod(datafile::FileHandle<'a>),
+    Loose(::std::fs::File),
+}
+impl<'a> ::std::io::Read for DataFile<'a>
+{
+    fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize>
+    {
+        match *self
+        {
+        DataFile::Pod(ref mut f) => f.read(buf),
+        DataFile::Loose(ref mut f) => f.read(buf),
+        }
+    }
 }
 
 struct EntityDef
@@ -68,6 +210,8 @@ struct EntityDef
     class: u8,
     model_a: String,
     model_b: String,
+    /// Starting health (guess: first value of line 2), zero for indestructible
+    hit_points: i32,
 
     drops: [(f32,u8); 2],
 
@@ -77,23 +221,92 @@ struct EntityRef
 {
     ty: usize,
     flags: u16,
-    // TODO: Use a 12.20 fixed point?
-    // - That's my guess of the type in the input files here.
-    x: f64,
-    y: f64,
-    z: f64,
+    // NOTE: 12.20 is a guess of the type in the input files
+    x: datafile::Fixed<datafile::fixed::Frac20>,
+    y: datafile::Fixed<datafile::fixed::Frac20>,
+    z: datafile::Fixed<datafile::fixed::Frac20>,
+}
+impl EntityRef
+{
+    fn render_position(&self, units: &world_units::WorldUnits) -> [f32; 3]
+    {
+        units.entity_position(self.x, self.y, self.z)
+    }
+}
+
+/// Model used for the player's ship
+// TODO: Confirm which model the original game uses
+const PLAYER_MODEL: &str = "LEAFSHIP.BIN";
+
+/// Seed for power-up drops (fixed, so a given sequence of play always drops the same items)
+const DROP_SEED: u64 = 0x4675_7279_3300;
+
+/// Level descriptor (in LEVELS) loaded at startup
+const LEVEL_FILE: &str = "EGYPT.LVL";
+
+/// Location of the original game's `SYSTEM` folder (containing the POD archives)
+const SYSTEM_DIR: &str = r"V:\Games\Fury3\SYSTEM";
+/// Environment variable that overrides `SYSTEM_DIR`
+const SYSTEM_DIR_VAR: &str = "FURY3_SYSTEM";
+/// Environment variable naming a directory of loose files (e.g. `MODELS\TENT2.BIN`) used in place of archive files,
+/// which are reloaded when they change
+const OVERRIDE_DIR_VAR: &str = "FURY3_OVERRIDE";
+
+fn system_dir() -> ::std::path::PathBuf
+{
+    match ::std::env::var_os(SYSTEM_DIR_VAR)
+    {
+    Some(v) => v.into(),
+    None => SYSTEM_DIR.into(),
+    }
 }
 
 fn main()
 {
     env_logger::init();
-    main_res().unwrap();
+    let args: Vec<String> = ::std::env::args().collect();
+    match args.get(1).map(|v| &v[..])
+    {
+    Some("map") => {
+        if args.len() != 4 {
+            eprintln!("Usage: {} map <LEVEL.LVL> <output.png>", args[0]);
+            ::std::process::exit(1);
+        }
+        if let Err(e) = level_map::render_to_file(&args[2], &args[3]) {
+            eprintln!("Unable to render map of {}: {}", args[2], e);
+            ::std::process::exit(1);
+        }
+        },
+    Some("--headless") => {
+        let ticks = match args.get(2).map(|v| v.parse())
+            {
+            None => HEADLESS_TICKS,
+            Some(Ok(v)) => v,
+            Some(Err(_)) => {
+                eprintln!("Usage: {} --headless [<ticks>]", args[0]);
+                ::std::process::exit(1);
+                },
+            };
+        main_headless(ticks).unwrap();
+        },
+    _ => main_res().unwrap(),
+    }
 }
 fn main_res() -> Result<(), BoxError>
 {
+    let mut root = GameRoot::new(PodFiles::open(system_dir())?, false);
+    // Anything below the sky cylinder (e.g. past the edge of the map) is left at the clear colour, so match the sky
+    let clear_colour = match root.sky_clear_colour(LEVEL_FILE)
+        {
+        Ok(v) => v,
+        Err(e) => {
+            warn!("Unable to get the sky colour of {}: {}", LEVEL_FILE, e);
+            Rgba(0., 0., 0., 1.)
+            },
+        };
     let pipe = ::amethyst::renderer::Pipeline::build().with_stage(
         ::amethyst::renderer::Stage::with_backbuffer()
-            .clear_target(Rgba(0.,0.2,0.,0.), 1.0)
+            .clear_target(clear_colour, 1.0)
             //.with_pass(::amethyst::renderer::DrawShadedSeparate::new())
             .with_pass(::amethyst::renderer::DrawFlatSeparate::new())
             ,
@@ -104,138 +317,316 @@ fn main_res() -> Result<(), BoxError>
 
     let config = ::amethyst::renderer::DisplayConfig::load(display_config_path);
 
-    let root = GameRoot {
-        pods: PodFiles {
-            startup: self::datafile::PodArchive::from_file(r"V:\Games\Fury3\SYSTEM\STARTUP.POD")?,
-            game: self::datafile::PodArchive::from_file(r"V:\Games\Fury3\SYSTEM\FURY3.POD")?,
-            }
-        };
     let mut game = Application::build("resources/assets", root)?
         .with_bundle(
             ::amethyst::input::InputBundle::<String, String>::new().with_bindings_from_file(&key_bindings_path),
             )?
         .with_bundle(::amethyst::renderer::RenderBundle::new())?
         .with_local(::amethyst::renderer::RenderSystem::build(pipe, Some(config))?)
-        .with(CameraMoveSystem::new(), "camera", &[])
+        .with(player::FlightSystem::default(), "flight", &[])
+        .with(camera::CameraSystem::new(), "camera", &["flight"])
+        .with(terrain_lod::TerrainLodSystem, "terrain_lod", &["camera"])
+        .with(sky::SkySystem, "sky", &["camera"])
+        .with(entity_state::CollisionSystem, "collision", &["flight"])
+        .with(entity_state::DestructionSystem, "destruction", &["collision"])
+        .with(powerups::DropSystem, "drops", &["collision"])
+        .with(powerups::PickupSystem, "pickup", &["flight", "drops"])
         .build()?;
     game.run();
     Ok(())
 }
 
+/// Default number of ticks for a headless run (one minute of game time)
+const HEADLESS_TICKS: u32 = 60 * 60;
+
+/// Run the retail level headless, then print the state of the player and the level entities
+fn main_headless(ticks: u32) -> Result<(), BoxError>
+{
+    let world = run_headless(PodFiles::open(system_dir())?, ticks);
+    {
+        use amethyst::ecs::Join;
+        for (ship, collector) in (&world.read::<player::PlayerShip>(), &world.read::<powerups::Collector>()).join()
+        {
+            println!("Player: position {:?}, speed {}, collected {:?}", ship.state.position, ship.state.speed, collector.items);
+        }
+        let health = world.read::<entity_state::Health>();
+        println!("Entities: {} destructible, {} destroyed", health.join().count(), health.join().filter(|h| h.is_dead()).count());
+    }
+    Ok(())
+}
+
+/// Build the world and step the simulation systems a fixed number of ticks, without a window or renderer
+fn run_headless(pods: PodFiles, ticks: u32) -> World
+{
+    let mut root = GameRoot::new(pods, true);
+    let mut world = World::new();
+    // Components and resources that would be added by the engine bundles
+    world.register::<Transform>();
+    world.register::<a_renderer::MeshHandle>();
+    world.register::<a_renderer::Material>();
+    world.add_resource(::amethyst::core::timing::Time::default());
+    world.add_resource(::amethyst::input::InputHandler::<String,String>::new());
+    root.on_start(&mut world);
+
+    let mut dispatcher = ecs::DispatcherBuilder::new()
+        .with(player::FlightSystem::default(), "flight", &[])
+        .with(entity_state::CollisionSystem, "collision", &["flight"])
+        .with(entity_state::DestructionSystem, "destruction", &["collision"])
+        .with(powerups::DropSystem, "drops", &["collision"])
+        .with(powerups::PickupSystem, "pickup", &["flight", "drops"])
+        .build();
+    for _ in 0 .. ticks
+    {
+        // Each tick is exactly one flight step, so runs are reproducible
+        world.write_resource::<::amethyst::core::timing::Time>().set_delta_seconds(flight::FIXED_DT);
+        dispatcher.dispatch(&mut world.res);
+        world.maintain();
+    }
+    world
+}
+
+/// Open a POD archive, warning about any problems with its file table
+fn open_archive(path: &::std::path::Path) -> ::std::io::Result<datafile::PodArchive>
+{
+    // Mapped if possible, so level loading doesn't go through a read call for each file
+    let archive = datafile::PodArchive::from_file_mapped(path)?;
+    let report = archive.check();
+    if !report.is_ok() {
+        warn!("{}: {} problems in the file table", path.display(), report.problems.len());
+        for p in &report.problems {
+            warn!("- {}", p);
+        }
+    }
+    Ok(archive)
+}
+
 impl PodFiles
 {
-    fn open_file(&mut self, path: DataPath) -> Result<datafile::FileHandle, ::std::io::Error>
+    /// The level from `datafile::synth` (plus the models that are loaded by name), with no override directory
+    #[cfg(test)]
+    fn synthetic() -> PodFiles
+    {
+        let (startup, game) = datafile::synth::level_archives("EGYPT", &[PLAYER_MODEL, "TENT2.BIN"]);
+        PodFiles {
+            startup: startup.to_archive().unwrap(),
+            game: game.to_archive().unwrap(),
+            system_dir: None,
+            overrides: None,
+            }
+    }
+
+    fn open<P: AsRef<::std::path::Path>>(system_dir: P) -> ::std::io::Result<PodFiles>
+    {
+        let overrides = ::std::env::var_os(OVERRIDE_DIR_VAR).map(::std::path::PathBuf::from);
+        if let Some(ref dir) = overrides {
+            debug!("Using loose files from {}", dir.display());
+        }
+        Ok(PodFiles {
+            startup: open_archive(&system_dir.as_ref().join("STARTUP.POD"))?,
+            game: open_archive(&system_dir.as_ref().join("FURY3.POD"))?,
+            system_dir: Some(system_dir.as_ref().to_owned()),
+            overrides: overrides,
+            })
+    }
+
+    /// Path of the loose file replacing `dir\file`, if there is one (the name can be in upper or lower case)
+    fn override_path(&self, dir: &str, file: &str) -> Option<::std::path::PathBuf>
     {
-        let a = match path.archive
+        let base = match self.overrides
             {
-            PodName::Startup => &mut self.startup,
-            PodName::Game => &mut self.game,
+            Some(ref v) => v.join(dir),
+            None => return None,
             };
-        let dirname = match path.folder
-            {
-            DataFolder::Art => "ART",
-            DataFolder::Data => "DATA",
-            DataFolder::Demo => "DEMO",
-            DataFolder::Fog => "FOG",
-            DataFolder::Levels => "LEVELS",
-            DataFolder::Models => "MODELS",
-            DataFolder::Music => "MUSIC",
-            DataFolder::Sound => "SOUND",
-            DataFolder::Startup => "STARTUP",
+        [file.to_owned(), file.to_ascii_uppercase(), file.to_ascii_lowercase()].iter()
+            .map(|f| base.join(f))
+            .find(|p| p.is_file())
+    }
+
+    fn open_file(&self, path: DataPath) -> Result<DataFile, ::std::io::Error>
+    {
+        if let Some(p) = self.override_path(path.folder.dir_name(), path.file) {
+            return Ok(DataFile::Loose( ::std::fs::File::open(p)? ));
+        }
+        Ok(DataFile::Pod( self.archive(path).open_dir_file(path.folder.dir_name(), path.file)? ))
+    }
+
+    /// Whole contents of a file, without copying if the archive is in memory
+    fn file_data(&self, path: DataPath) -> Result<::std::borrow::Cow<[u8]>, ::std::io::Error>
+    {
+        if let Some(p) = self.override_path(path.folder.dir_name(), path.file) {
+            return Ok(::std::borrow::Cow::Owned( ::std::fs::read(p)? ));
+        }
+        self.archive(path).dir_file_data(path.folder.dir_name(), path.file)
+    }
+
+    /// Contents of a file by its full name (e.g. `MODELS\TENT2.BIN`), from either archive
+    fn file_data_by_name(&self, name: &str) -> Result<::std::borrow::Cow<[u8]>, ::std::io::Error>
+    {
+        if let Some(p) = name.find('\\').and_then(|i| self.override_path(&name[..i], &name[i+1..])) {
+            return Ok(::std::borrow::Cow::Owned( ::std::fs::read(p)? ));
+        }
+        let name = name.to_ascii_uppercase();
+        self.game.file_data(&name).or_else(|_| self.startup.file_data(&name))
+    }
+
+    /// Modification time (seconds since the epoch) of a file by its full name
+    ///
+    /// This is the time of the loose file replacing it if there is one, or else of the archive it's in (zero for
+    /// archives only in memory).
+    fn modified_by_name(&self, name: &str) -> Result<u64, ::std::io::Error>
+    {
+        let mtime = |p: &::std::path::Path| -> Result<u64, ::std::io::Error> {
+            let t = ::std::fs::metadata(p)?.modified()?;
+            Ok( t.duration_since(::std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0) )
+            };
+        if let Some(p) = name.find('\\').and_then(|i| self.override_path(&name[..i], &name[i+1..])) {
+            return mtime(&p);
+        }
+        let name = name.to_ascii_uppercase();
+        let pod_name = if self.game.open_file(&name).is_ok() {
+                "FURY3.POD"
+            }
+            else if self.startup.open_file(&name).is_ok() {
+                "STARTUP.POD"
+            }
+            else {
+                return Err(::std::io::Error::new(::std::io::ErrorKind::NotFound, format!("{} not found", name)));
             };
-        a.open_dir_file(dirname, path.file)
+        match self.system_dir
+        {
+        Some(ref dir) => mtime(&dir.join(pod_name)),
+        None => Ok(0),
+        }
+    }
+
+    fn archive(&self, path: DataPath) -> &datafile::PodArchive
+    {
+        match path.archive
+        {
+        PodName::Startup => &self.startup,
+        PodName::Game => &self.game,
+        }
+    }
+
+    /// Load a 256 entry RGB palette (`.ACT`)
+    fn load_palette(&self, path: DataPath) -> Result<Vec<u8>, BoxError>
+    {
+        use ::std::io::Read;
+        let mut fh = self.open_file(path)?;
+        let mut rv = vec![0; 256*3];
+        fh.read_exact(&mut rv)?;
+        Ok(rv)
+    }
+
+    /// Decode a `.BIN` model into mesh data (ready to hand to the asset loader)
+    fn decode_model(&self, model_path: DataPath) -> Result<a_renderer::MeshData, BoxError>
+    {
+        let m = datafile::Model::from_bin_file( self.open_file(model_path)? )?;
+        Ok( pod_assets::model_mesh(&m) )
+    }
+
+    /// Decode a paletted `.RAW` texture into RGBA, returning its size and pixels
+    fn decode_texture(&self, path: DataPath, palette: &[u8]) -> Result<(usize, Vec<u8>), BoxError>
+    {
+        let pixels = self.file_data(path)?;
+        Ok( pod_assets::paletted_rgba(&pixels, palette)? )
     }
 }
 
-impl GameRoot
+/// Run `load` on each item using Amethyst's thread pool, returning the results in the same order as `items`
+///
+/// Errors are returned as strings, as `BoxError` can't be sent between threads.
+fn load_parallel<I, T, F>(pool: &::amethyst::core::rayon::ThreadPool, pods: &PodFiles, items: &[I], load: F) -> Vec<Result<T, String>>
+where
+    I: Sync,
+    T: Send,
+    F: Fn(&PodFiles, &I) -> Result<T, BoxError> + Sync,
 {
-    fn load_blue_material(&mut self, world: &mut World) -> a_renderer::Material
+    let mut rv: Vec<_> = items.iter().map(|_| Err("not loaded".to_owned())).collect();
     {
-        // Colour/material
-        let tex_storage = world.read_resource();
-        let mat_defaults = world.read_resource::<a_renderer::MaterialDefaults>();
-
-        let loader = world.read_resource::<::amethyst::assets::Loader>();
-        let albedo = [0.0, 0.0, 1.0, 1.0].into();
-        let albedo = loader.load_from_data(albedo, (), &tex_storage);
-        a_renderer::Material {
-            albedo,
-            emission: loader.load_from_data([0.0, 0.0, 0.0, 1.0].into(), (), &tex_storage),
-            ..mat_defaults.0.clone()
+        let load = &load;
+        pool.scope(|s| {
+            for (slot, item) in rv.iter_mut().zip(items)
+            {
+                s.spawn(move |_| *slot = load(pods, item).map_err(|e| e.to_string()));
             }
+            });
     }
+    rv
+}
 
-    fn load_model(&mut self, world: &mut World, model_path: DataPath) -> Result<(::amethyst::assets::Handle<a_renderer::Mesh>, a_renderer::Material), BoxError>
+impl GameRoot
+{
+    fn new(pods: PodFiles, headless: bool) -> GameRoot
     {
-        const SCALE: f32 = 1. / 100.;
-        let m = datafile::Model::from_bin_file( self.pods.open_file(model_path)? )?;
-        let vertices_as_arrays: Vec<_> = m.faces.iter()
-            .flat_map(|v| v.v.iter().map(|&v| m.vertices[v as usize]))
-            .map(|v| [v[0] * SCALE, v[1] * SCALE, v[2] * SCALE])
-            .collect();
-        debug!("vertices_as_arrays.len() = {}", vertices_as_arrays.len());
-        let normals: Vec<_> = m.faces.iter()
-            .flat_map(|v| {
-                let n = v.normal;
-                v.v.iter().map(move |_| a_renderer::Separate::<a_renderer::Normal>::new(Vector3::from(n).into()))
-                })
-            .collect();
-        let tex_coords: Vec<_> = m.faces.iter()
-            .flat_map(|v| {
-                v.v.iter().map(move |_| a_renderer::Separate::<a_renderer::TexCoord>::new([0.1,0.1]))
-                })
-            .collect();
+        let pods = ::std::sync::Arc::new(pods);
+        GameRoot {
+            assets: asset_cache::AssetCache::new(pods.clone()),
+            pods: pods,
+            presses: controls::PressTracker::default(),
+            headless: headless,
+            level_files: LevelFiles::default(),
+            level_entities: Vec::new(),
+            watcher: None,
+            sky: None,
+            }
+    }
 
-        let mesh: ::amethyst::assets::Handle<a_renderer::Mesh> = {
-            let loader = world.read_resource::<::amethyst::assets::Loader>();
-            let m2: a_renderer::ComboMeshCreator = (
-                vertices_as_arrays.into_iter().map(|p| a_renderer::Separate::<a_renderer::Position>::new(p)).collect::<Vec<_>>(),
-                None,   // TODO: Colours
-                Some(tex_coords),   // Texture coords (needed)
-                Some(normals),   // TODO: Normals
-                None,   // TODO: Tangents
-                ).into();
-            loader.load_from_data(m2.into(), (), &world.read_resource())
-            };
+    fn load_blue_material(&mut self, world: &mut World) -> a_renderer::Material
+    {
+        self.assets.solid_material(world, [0.0, 0.0, 1.0, 1.0])
+    }
 
+    fn load_model(&mut self, world: &mut World, model_path: DataPath) -> Result<(a_renderer::MeshHandle, a_renderer::Material), BoxError>
+    {
+        let mesh = self.assets.mesh(world, model_path)?;
         let mat = self.load_blue_material(world);
 
         Ok( (mesh, mat) )
     }
 
-    fn load_level_material(&mut self, world: &mut World, list_file: DataPath, default_plt: DataPath)
-            -> Result< (a_renderer::Material, Vec<(usize, usize, usize)>), BoxError>
-    {   
-        use ::std::io::Read;
+    /// Load a `.LVL` level descriptor from the LEVELS folder
+    fn load_level_file(&mut self, name: &str) -> Result<datafile::Level, BoxError>
+    {
+        Ok( datafile::Level::from_file( self.pods.open_file(datapath!(Game, Levels, name))? )? )
+    }
 
+    /// Load a `.TEX` texture list (names of `.RAW` files in ART)
+    fn load_texture_list(&mut self, list_file: DataPath) -> Result<Vec<String>, BoxError>
+    {
+        use ::std::io::Read;
         let mut file_list_data = String::new();
-        let file_list: Vec<&str> = {
-            let mut list_file = self.pods.open_file(list_file)?;
-            list_file.read_to_string(&mut file_list_data)?;
-            let mut it = file_list_data.split("\r\n");
-            let _count = it.next();
-            let mut v: Vec<_> = it.collect();
-            v.pop();
-            v
-            };
+        self.pods.open_file(list_file)?.read_to_string(&mut file_list_data)?;
+        let mut it = file_list_data.split("\r\n");
+        let _count = it.next();
+        let mut v: Vec<_> = it.map(|v| v.to_owned()).collect();
+        v.pop();
+        Ok(v)
+    }
 
-        let default_plt = {
-            let mut fh = self.pods.open_file( default_plt )?;
-            let mut rv = vec![0; 256*3];
-            fh.read(&mut rv)?;
-            rv
-            };
+    /// Pack the level's textures into one RGBA texture, returning its width, height and pixels, and where each texture
+    /// (in `.TEX` order) was placed
+    fn load_level_atlas(&mut self, world: &World, list_file: DataPath, default_plt: DataPath)
+            -> Result< (usize, usize, Vec<u8>, Vec<terrain::AtlasRect>), BoxError>
+    {
+        let file_list = self.load_texture_list(list_file)?;
+        self.assets.palette(default_plt)?;
 
-        // 1. Determine max texture size
-        // TODO: Pack the textures into an efficient format
-        let mut sizes = Vec::new();
-        for &name in &file_list
+        // Each texture uses the .ACT with the same name if there is one, otherwise the level palette
+        let act_names: Vec<_> = file_list.iter().map(|name| format!("{}ACT", &name[..name.len() - 3])).collect();
+        let mut paths = Vec::new();
+        for (name, act_fname) in file_list.iter().zip(&act_names)
         {
-            let size = self.pods.open_file( datapath!(Game, Art, name) )?.size();
-            let dim = (size as f64).sqrt() as usize;
-            assert_eq!(dim*dim, size);
-            sizes.push(dim);
+            let plt = datapath!(Game, Art, act_fname);
+            let plt = if self.assets.find_palette(plt)?.is_some() { plt } else { default_plt };
+            paths.push( (datapath!(Game, Art, name), plt) );
         }
+        // Textures that aren't already cached are decoded on the loader threads
+        let textures = self.assets.texture_rgba(world, &paths)?;
+
+        // 1. Determine max texture size
+        // TODO: Pack the textures into an efficient format
+        let sizes: Vec<_> = textures.iter().map(|t| t.0).collect();
         let max_width = sizes.iter().cloned().max().unwrap();
         //   - Check that all sizes are powers of two?
         // - Pack into a strip, with the width being the max texture width.
@@ -243,7 +634,7 @@ impl GameRoot
             // - Sort by size (remembering index)
             let mut indexes: Vec<_> = (0.. sizes.len()).collect();
             indexes.sort_by_key(|&v| sizes[v]);
-            let mut subtex_coords = vec![ (0,0,0); sizes.len() ];
+            let mut subtex_coords = vec![ terrain::AtlasRect { x: 0, y: 0, dim: 0 }; sizes.len() ];
             let mut h = 0;
             let mut x_space = 0;
             for v in indexes
@@ -253,7 +644,7 @@ impl GameRoot
                     h += sizes[v];
                 }
 
-                subtex_coords[v] = (max_width - x_space, h - sizes[v], sizes[v]);
+                subtex_coords[v] = terrain::AtlasRect { x: max_width - x_space, y: h - sizes[v], dim: sizes[v] };
 
                 x_space -= sizes[v];
             }
@@ -265,55 +656,32 @@ impl GameRoot
         let pitch = max_width*4;
         let mut tex_data = vec![ 0; total_height*pitch ];
 
-        // 3. Re-load every texture into the file.
-        for (i, &name) in file_list.iter().enumerate()
+        // 3. Copy every texture into the atlas
+        for (i, (name, texture)) in file_list.iter().zip(textures.iter()).enumerate()
         {
-            // Make a second version of the name that is .ACT instead of .RAW
-            let self_plt;
-            let palette = {
-                    let act_fname = format!("{}ACT", &name[..name.len() - 3]);
-                    if let Ok(mut fh) = self.pods.open_file( datapath!(Game, Art, &act_fname) ) {
-                        let mut rv = vec![0; 256*3];
-                        fh.read(&mut rv)?;
-                        self_plt = rv;
-                        &self_plt
-                    }
-                    else {
-                        &default_plt
-                    }
-                };
-            let mut fh = self.pods.open_file( datapath!(Game, Art, name) )?;
-            let dim = (fh.size() as f64).sqrt() as usize;
-
-            assert_eq!(subtex_coords[i].2, dim);
-            let mut ofs = subtex_coords[i].1 * pitch + subtex_coords[i].0 * 4;
+            let (dim, ref pixels) = **texture;
+            assert_eq!(subtex_coords[i].dim, dim);
+            let mut ofs = subtex_coords[i].y * pitch + subtex_coords[i].x * 4;
             debug!("load_level_texture: {} {:?} @ {},{}+{} - ofs={:#x} dim={}",
                 i, name,
-                subtex_coords[i].0, subtex_coords[i].1, subtex_coords[i].2,
+                subtex_coords[i].x, subtex_coords[i].y, subtex_coords[i].dim,
                 ofs, dim);
-            for _ in 0 .. dim
+            for row in pixels.chunks(dim*4)
             {
-                //debug!("> ofs={:#x}", ofs);
-                let dst = &mut tex_data[ofs ..][.. dim*4];
-
-                for x in 0 .. dim
-                {
-                    let b = {
-                        let mut b = [0];
-                        fh.read(&mut b)?;
-                        b[0]
-                        };
-                    dst[x*4 + 0] = palette[b as usize * 3 + 0];
-                    dst[x*4 + 1] = palette[b as usize * 3 + 1];
-                    dst[x*4 + 2] = palette[b as usize * 3 + 2];
-                    dst[x*4 + 3] = 255;
-                }
-
+                tex_data[ofs ..][.. dim*4].copy_from_slice(row);
                 ofs += pitch;
             }
             debug!("> ofs={:#x} / {:#x}", ofs, tex_data.len());
         }
         debug!("Loaded texture set {:?} - {}KiB RGBA uncompressed", list_file, tex_data.len() / 1024);
+        Ok( (max_width, total_height, tex_data, subtex_coords) )
+    }
+
+    fn load_level_material(&mut self, world: &mut World, list_file: DataPath, default_plt: DataPath)
+            -> Result< (a_renderer::Material, Vec<terrain::AtlasRect>), BoxError>
+    {   
+        let (width, total_height, tex_data, subtex_coords) = self.load_level_atlas(world, list_file, default_plt)?;
+        let pitch = width*4;
 
         if true
         {
@@ -340,19 +708,7 @@ impl GameRoot
         }
 
         let loader = world.read_resource::<::amethyst::assets::Loader>();
-        let tex = a_renderer::TextureData::U8(tex_data,
-            a_renderer::TextureMetadata {
-                sampler: Some(::gfx_core::texture::SamplerInfo::new(
-                    ::gfx_core::texture::FilterMethod::Bilinear,
-                    ::gfx_core::texture::WrapMode::Clamp,
-                    )),  // TODO: Add a sampler?
-                mip_levels: None,
-                size: Some(( (pitch/4) as u16, total_height as u16 )),
-                dynamic: false,
-                format: Some(::gfx_core::format::SurfaceType::R8_G8_B8_A8),
-                channel: None,//Some(::gfx_core::format::ChannelType::Uint),
-                }
-            );
+        let tex = pod_assets::rgba_texture(pitch/4, total_height, tex_data, ::gfx_core::texture::WrapMode::Clamp);
         let tex: a_renderer::TextureHandle = loader.load_from_data(tex, (), &world.read_resource());
 
         let mat_defaults = world.read_resource::<a_renderer::MaterialDefaults>();
@@ -368,273 +724,241 @@ impl GameRoot
             }, subtex_coords) )
     }
 
-    fn load_heightmap(&mut self, world: &mut World, model_path: DataPath, texture_widths: &[(usize,usize,usize)]) -> Result<::amethyst::assets::Handle<a_renderer::Mesh>, BoxError>
+    fn load_heightmap(&mut self, model_path: DataPath, textures: Vec<terrain::AtlasRect>) -> Result<terrain::Terrain, BoxError>
     {
-        use std::io::Read;
         let clr_fname = format!("{}CLR", &model_path.file[..model_path.file.len() - 3]);
 
-        fn with_row_pairs<F>(pods: &mut PodFiles, p: DataPath, mut cb: F) -> ::std::io::Result<()>
-        where
-            F: FnMut(usize, &[u8], &[u8])
-        {
-            let mut file = pods.open_file(p)?;
-            let dim = (file.size() as f64).sqrt() as usize;
-            assert_eq!(dim*dim, file.size());
-
-            let mut prev_row = vec![0u8; dim];
-            let mut cur_row = vec![0u8; dim];
-            file.read(&mut cur_row)?;
-            for r in 1 .. dim
-            {
-                ::std::mem::swap(&mut prev_row, &mut cur_row);
-                file.read(&mut cur_row)?;
-
-                cb(r, &prev_row, &cur_row);
-            }
-            Ok( () )
-        }
-
-        let triangle_verts = {
-            let h_scale = 1. / 256.;
-            let xy_scale = 1. / 8.;
+        let heights = terrain::ByteGrid::from_reader( self.pods.open_file(model_path)? )?;
+        let colours = terrain::ByteGrid::from_reader( self.pods.open_file(datapath!(Game, Data, &clr_fname))? )?;
+        let terrain = terrain::Terrain::new(heights, colours, textures)?;
+        debug!("load_heightmap: {0}x{0} split into {1}x{1} chunks", terrain.heights().dim(), terrain.chunk_count());
 
-            let mut triangle_verts = vec![];
-            with_row_pairs(&mut self.pods, model_path, |r, prev_row, cur_row| {
-                assert_eq!( prev_row.len(), cur_row.len() );
-                let xy_ofs = (cur_row.len() / 2) as f32 * xy_scale;
-                // Make triangles for each quad.
-                for c in 1 .. prev_row.len()
-                {
-                    let pt_tl = [(c-1) as f32 * xy_scale - xy_ofs, prev_row[c-1] as f32 * h_scale, (r-1) as f32 * xy_scale - xy_ofs, ];
-                    let pt_tr = [(c  ) as f32 * xy_scale - xy_ofs, prev_row[c  ] as f32 * h_scale, (r-1) as f32 * xy_scale - xy_ofs, ];
-                    let pt_bl = [(c-1) as f32 * xy_scale - xy_ofs, cur_row [c-1] as f32 * h_scale, (r  ) as f32 * xy_scale - xy_ofs, ];
-                    let pt_br = [(c  ) as f32 * xy_scale - xy_ofs, cur_row [c  ] as f32 * h_scale, (r  ) as f32 * xy_scale - xy_ofs, ];
-                    // BottomLeft, TopRight, TopLeft
-                    triangle_verts.push(pt_bl);
-                    triangle_verts.push(pt_tr);
-                    triangle_verts.push(pt_tl);
-                    // BottomLeft, BottomRight, TopRight
-                    triangle_verts.push( pt_bl );
-                    triangle_verts.push( pt_br );
-                    triangle_verts.push( pt_tr );
-                }
+        Ok( terrain )
+    }
 
-                })?;
-            triangle_verts
-            };
-        
-        // TODO: Textures
-        // - Load a massive texture from the flies listed in `LEVEL.TEX`
-        // - Get indexes from `LEVEL.CLR`
-        let tex_coords: Vec<a_renderer::Separate<a_renderer::TexCoord>> = {
-            let tex_width : usize = texture_widths.iter().map(|v| v.0+v.2).max().unwrap();
-            let tex_height: usize = texture_widths.iter().map(|v| v.1+v.2).max().unwrap();
-
-            let mut tex_coords = Vec::with_capacity(triangle_verts.len());
-            with_row_pairs(&mut self.pods, datapath!(Game, Data, &clr_fname), |_r, prev_row, _cur_row| {
-                for c in 1 .. prev_row.len()
-                {
-                    let tex_id = prev_row[c-1] as usize;
-                    let base_u = texture_widths[tex_id].0 as f32 / tex_width as f32;
-                    let base_v = texture_widths[tex_id].1 as f32 / tex_height as f32;
-                    let w = (texture_widths[tex_id].2 as f32 - 1.) / tex_width as f32;
-                    let h = (texture_widths[tex_id].2 as f32 - 1.) / tex_height as f32;
-
-                    let tex_tl = [ base_u  , 1.0 - (base_v  ) ];    // Lowest  U, Lowest V
-                    let tex_tr = [ base_u+w, 1.0 - (base_v  ) ];    // Highest U, Lowest V
-                    let tex_bl = [ base_u  , 1.0 - (base_v+h) ];    // Lowest  U, Highest V
-                    let tex_br = [ base_u+w, 1.0 - (base_v+h) ];    // Highest U, Highest V
-                    //let (tex_tl, tex_tr, tex_br, tex_bl, ) = (tex_tr, tex_bl, tex_tl, tex_br, );
-                    // - TODO: Fix texturing, assignment is wrong again.
-                    // BottomLeft, TopRight, TopLeft
-                    tex_coords.push( a_renderer::Separate::new(tex_bl) );
-                    tex_coords.push( a_renderer::Separate::new(tex_tr) );
-                    tex_coords.push( a_renderer::Separate::new(tex_tl) );
-                    // BottomLeft, BottomRight, TopRight
-                    tex_coords.push( a_renderer::Separate::new(tex_bl) );
-                    tex_coords.push( a_renderer::Separate::new(tex_br) );
-                    tex_coords.push( a_renderer::Separate::new(tex_tr) );
-                }
-                })?;
-            tex_coords
-            };
-        let normals = triangle_verts.iter()
-            .map(|_v| {
-                // TODO: Calculate a normal based on the surface.
-                a_renderer::Separate::new([ 0.0, 1.0, 0.0 ])
+    fn load_entities_file(&mut self, path: DataPath) -> Result<(Vec<EntityDef>, Vec<EntityRef>), BoxError>
+    {
+        let file = datafile::EntityFile::from_file( self.pods.open_file(path)? )?;
+
+        let def_list = file.types.into_iter()
+            .map(|t| {
+                debug!("Entity description '{}'", t.description);
+                EntityDef {
+                    class: t.class,
+                    model_a: t.model,
+                    model_b: t.model_destroyed,
+                    hit_points: ::std::cmp::max(0, ::std::cmp::min(t.unk_line2.get(0).cloned().unwrap_or(0), ::std::i32::MAX as i64)) as i32,
+
+                    // Empty slots get a zero chance, so are never rolled
+                    drops: [
+                        t.drop(0).map(|(chance, item)| (chance as f32 / 100., item)).unwrap_or((0., 0)),
+                        t.drop(1).map(|(chance, item)| (chance as f32 / 100., item)).unwrap_or((0., 0)),
+                        ],
+
+                    description: t.description,
+                    }
                 })
-            .collect()
-            ;
-
-        let m2: a_renderer::ComboMeshCreator = (
-            triangle_verts.into_iter().map(|p| a_renderer::Separate::<a_renderer::Position>::new(p)).collect::<Vec<_>>(),
-            None,   // Colours
-            Some(tex_coords),   // Texture coords (needed)
-            Some(normals),   // Normals
-            None,   // TODO: Tangents?
-            ).into();
+            .collect();
 
-        let loader = world.read_resource::<::amethyst::assets::Loader>();
-        let mesh: ::amethyst::assets::Handle<a_renderer::Mesh> = loader.load_from_data(m2.into(), (), &world.read_resource());
+        let ent_list = file.placements.iter()
+            .map(|p| EntityRef {
+                ty: p.ty,
+                flags: p.flags,
+                x: p.x,
+                y: p.y,
+                z: p.z,
+                })
+            .collect();
 
-        Ok( mesh )
+        Ok( (def_list, ent_list) )
     }
 
-    fn load_entities_file(&mut self, path: DataPath) -> Result<(Vec<EntityDef>, Vec<EntityRef>), BoxError>
+    /// Mesh and material for each entity type (if it loaded), and the destroyed mesh of those that have one (none when
+    /// headless)
+    fn load_entity_models(&mut self, world: &mut World, entity_types: &[EntityDef])
+        -> (Vec<Option<(a_renderer::MeshHandle, a_renderer::Material)>>, Vec<Option<a_renderer::MeshHandle>>)
     {
-        let mut fp = ::std::io::BufReader::new( self.pods.open_file(path)? );
-
-        fn read_line<'a, F: ::std::io::BufRead>(f: &mut F, line: &'a mut String) -> Result<&'a str, BoxError> {
-            line.clear();
-            f.read_line(line)?;
-            let len = line.trim_right().len();
-            line.truncate(len);
-            Ok(&line[..])
-        }
-        let mut line = String::new();
-
-        // 1. Read the entity count.
-        let ty_count: usize = read_line(&mut fp, &mut line)?.parse().unwrap();
-        let mut def_list = Vec::new();
-        for _ in 0 .. ty_count
+        let mut model_mats = Vec::new();
+        let mut destroyed_meshes = Vec::new();
+        if !self.headless
         {
-            let class: u8;
-            let model: String;
-            let model_destroyed: String;
-            let drops: [(u8,i8); 2];
-            let desc;
-            // Line 1: Model/general information
-            {
-                read_line(&mut fp, &mut line)?;
-                let mut it = line.split(',');
-                class = it.next().unwrap().parse().unwrap();
-                let _ = it.next().unwrap();
-                let _ = it.next().unwrap();
-                let _ = it.next().unwrap();
-                let _ = it.next().unwrap();
-                let _ = it.next().unwrap();
-                model = it.next().unwrap().to_owned();
-                model_destroyed = it.next().unwrap().to_owned();
-            }
-            // Line 2: Unknown
-            {
-                read_line(&mut fp, &mut line)?;
-                let mut it = line.split(',');
-                let _ = it.next().unwrap();
-                let _ = it.next().unwrap();
-                let _ = it.next().unwrap();
-                let _ = it.next().unwrap();
-                let _ = it.next().unwrap();
-            }
-            // Line 3: Drop info
-            {
-                read_line(&mut fp, &mut line)?;
-                let mut it = line.split(',');
-                drops = [
-                    (it.next().unwrap().parse().expect("drop perc 1"), it.next().unwrap().parse().expect("drop item 1"), ),
-                    (it.next().unwrap().parse().expect("drop perc 2"), {let v = it.next().unwrap(); debug!("{:?}", v); v.parse().expect("drop item 2")}, ),
-                    ];
-            }
-            // Line 4: Unknown
-            {
-                read_line(&mut fp, &mut line)?;
-                let mut _it = line.split(',');
-            }
-            // Line 5: ";NewHit"
+            // Decode the models on the loader threads first, types sharing a model then get the same handle
+            let names: Vec<_> = entity_types.iter().flat_map(|e| vec![e.model_a.clone(), e.model_b.clone()]).collect();
+            self.assets.preload_models(world, &names);
+            for e in entity_types
             {
-                read_line(&mut fp, &mut line)?;
-                assert_eq!(line, ";NewHit")
-            }
-            // Line 6: Unknown
-            {
-                read_line(&mut fp, &mut line)?;
-                let mut _it = line.split(',');
-            }
-            // Line 7: "!NewAtakRet"
-            {
-                read_line(&mut fp, &mut line)?;
-                assert_eq!(line, "!NewAtakRet")
-            }
-            // Line 8: Unknown
-            {
-                read_line(&mut fp, &mut line)?;
-                let mut _it = line.split(',');
-            }
-            // Line 9: Description
-            {
-                read_line(&mut fp, &mut line)?;
-                desc = line.clone();
-                debug!("Entity description '{}'", desc);
-            }
-            // Line 10: "#New2ndweapon"
-            {
-                read_line(&mut fp, &mut line)?;
-                assert_eq!(line, "#New2ndweapon")
+                debug!("Load {:?}/{:?} '{}'", e.model_a, e.model_b, e.description);
+                // A missing or broken model shouldn't stop the level loading, the entities are just left invisible
+                model_mats.push(match self.load_model(world, datapath!(Game, Models, &e.model_a))
+                    {
+                    Ok(v) => Some(v),
+                    Err(err) => {
+                        warn!("Unable to load model {:?} for '{}': {}", e.model_a, e.description, err);
+                        None
+                        },
+                    });
+                destroyed_meshes.push(match self.assets.mesh(world, datapath!(Game, Models, &e.model_b))
+                    {
+                    Ok(mesh) => Some(mesh),
+                    Err(err) => {
+                        warn!("Unable to load destroyed model {:?} for '{}': {}", e.model_b, e.description, err);
+                        None
+                        },
+                    });
             }
-            // Line 11: Unknown
-            {
-                read_line(&mut fp, &mut line)?;
-                let mut _it = line.split(',');
+        }
+        (model_mats, destroyed_meshes)
+    }
+
+    /// Apply a changed file from the override directory to the running level
+    fn reload_file(&mut self, world: &mut World, folder: DataFolder, name: &str) -> Result<(), BoxError>
+    {
+        let files = self.level_files.clone();
+        let ext = name.rsplit('.').next().unwrap_or("");
+        debug!("Reloading {:?} {}", folder, name);
+        match (folder, ext)
+        {
+        (DataFolder::Models, "BIN") => self.reload_model(world, name),
+        (DataFolder::Art, _) if name == files.sky_texture || name == files.sky_palette || name == files.sky_texture_palette() => {
+            self.load_sky(world);
+            // The sky may share the terrain's palette
+            if name == files.palette {
+                self.reload_terrain(world)?;
             }
-            // Line 12: "%SFX"
+            Ok( () )
+            },
+        (DataFolder::Art, "ACT") => {
+            self.assets.forget_palette(datapath!(Game, Art, name));
+            self.reload_terrain(world)
+            },
+        (DataFolder::Art, "RAW") => {
+            self.assets.forget_texture(datapath!(Game, Art, name));
+            self.reload_terrain(world)
+            },
+        (DataFolder::Data, "DEF") if name == files.entities => self.reload_entities(world),
+        (DataFolder::Data, _) if name == files.heightmap || name == files.colour_map() || name == files.texture_list => self.reload_terrain(world),
+        _ => {
+            debug!("- Not used by the level");
+            Ok( () )
+            },
+        }
+    }
+
+    /// Reload a model, and switch every entity using the old mesh to the new one
+    fn reload_model(&mut self, world: &mut World, name: &str) -> Result<(), BoxError>
+    {
+        use amethyst::ecs::Join;
+        let (old, new) = match self.assets.reload_mesh(world, datapath!(Game, Models, name))?
             {
-                read_line(&mut fp, &mut line)?;
-                assert_eq!(line, "%SFX")
+            (Some(old), new) => (old, new),
+            // Not used yet, so nothing to update
+            (None, _) => return Ok( () ),
+            };
+        for mesh in (&mut world.write::<a_renderer::MeshHandle>()).join()
+        {
+            if mesh.id() == old.id() {
+                *mesh = new.clone();
             }
-            // Line 13: Unknown filename
-            {
-                read_line(&mut fp, &mut line)?;
+        }
+        for d in (&mut world.write::<entity_state::Destructible>()).join()
+        {
+            if d.destroyed_mesh.id() == old.id() {
+                d.destroyed_mesh = new.clone();
             }
-            // Line 14: Unknown filename
+        }
+        Ok( () )
+    }
+
+    /// Rebuild the terrain (heightmap, colour map and texture atlas) and update the chunk entities
+    fn reload_terrain(&mut self, world: &mut World) -> Result<(), BoxError>
+    {
+        use amethyst::ecs::Join;
+        let files = self.level_files.clone();
+        let (mat, tex_scales) = self.load_level_material(world, datapath!(Game, Data, &files.texture_list), datapath!(Game, Art, &files.palette))?;
+        let terrain = self.load_heightmap(datapath!(Game, Data, &files.heightmap), tex_scales)?;
+        if terrain.chunk_count() != world.read_resource::<terrain::Terrain>().chunk_count() {
+            return Err("Heightmap size changed, restart to load it".into());
+        }
+        {
+            let loader = world.read_resource::<::amethyst::assets::Loader>();
+            let mesh_storage = world.read_resource::<::amethyst::assets::AssetStorage<a_renderer::Mesh>>();
+            let mut chunks = world.write::<terrain_lod::TerrainChunkRef>();
+            let mut meshes = world.write::<a_renderer::MeshHandle>();
+            let mut materials = world.write::<a_renderer::Material>();
+            for (ent, chunk, material) in (&*world.entities(), &mut chunks, &mut materials).join()
             {
-                read_line(&mut fp, &mut line)?;
+                *chunk = terrain_lod::TerrainChunkRef::build(&loader, &mesh_storage, &terrain, chunk.cx, chunk.cz, chunk.lod);
+                // Culled chunks get their mesh back from `TerrainLodSystem` when they're visible again
+                if meshes.get(ent).is_some() {
+                    meshes.insert(ent, chunk.mesh.clone());
+                }
+                *material = mat.clone();
             }
+        }
+        world.add_resource(terrain);
+        Ok( () )
+    }
 
-            def_list.push(EntityDef {
-                class: class,
-                model_a: model,
-                model_b: model_destroyed,
+    /// Create the sky from the level's sky texture, or update its material if it already exists
+    fn load_sky(&mut self, world: &mut World)
+    {
+        let files = self.level_files.clone();
+        // Loaded through the asset loader (in the background), which reports any error
+        let tex: a_renderer::TextureHandle = {
+            let loader = world.read_resource::<::amethyst::assets::Loader>();
+            let palette = format!(r"ART\{}", files.sky_palette);
+            loader.load_from(format!(r"ART\{}", files.sky_texture), pod_assets::RawFormat, pod_assets::SOURCE, Some(palette), (), &world.read_resource())
+            };
+        let material = a_renderer::Material {
+            albedo: tex,
+            ..world.read_resource::<a_renderer::MaterialDefaults>().0.clone()
+            };
+        match self.sky
+        {
+        Some(ent) => { world.write::<a_renderer::Material>().insert(ent, material); },
+        None => {
+            let mesh = upload_mesh(world, sky::cylinder_mesh());
+            self.sky = Some(sky::initialise(world, mesh, material));
+            },
+        }
+    }
 
-                drops: [
-                    (drops[0].0 as f32 / 100., drops[0].1 as u8),
-                    (drops[1].0 as f32 / 100., drops[1].1 as u8),
-                    ],
+    /// Average colour of the bottom row of a level's sky texture
+    fn sky_clear_colour(&mut self, level_file: &str) -> Result<Rgba, BoxError>
+    {
+        let files = LevelFiles::from_level( &self.load_level_file(level_file)? );
+        // The same palette as `pod_assets::RawFormat` uses
+        let palette = match self.assets.find_palette(datapath!(Game, Art, &files.sky_texture_palette()))?
+            {
+            Some(v) => v,
+            None => self.assets.palette(datapath!(Game, Art, &files.sky_palette))?,
+            };
+        let (dim, rgba) = self.pods.decode_texture(datapath!(Game, Art, &files.sky_texture), &palette)?;
+        let c = sky::bottom_row_colour(dim, &rgba);
+        Ok( Rgba(c[0], c[1], c[2], c[3]) )
+    }
 
-                description: desc,
-                });
+    /// Re-read the entity file, updating the existing entities in placement order (and adding or removing extras)
+    fn reload_entities(&mut self, world: &mut World) -> Result<(), BoxError>
+    {
+        let files = self.level_files.clone();
+        let (entity_types, entity_list) = self.load_entities_file(datapath!(Game, Data, &files.entities))?;
+        let (model_mats, destroyed_meshes) = self.load_entity_models(world, &entity_types);
+        for (i, e) in entity_list.iter().enumerate()
+        {
+            if i == self.level_entities.len() {
+                let ent = world.create_entity().build();
+                self.level_entities.push(ent);
+            }
+            let ent = self.level_entities[i];
+            place_entity(world, ent, e, &entity_types[e.ty], model_mats.get(e.ty).cloned().and_then(|v| v), destroyed_meshes.get(e.ty).cloned().and_then(|v| v));
         }
-
-        // --------------
-        let ent_count: usize = read_line(&mut fp, &mut line)?.parse().unwrap();
-        let mut ent_list = Vec::new();
-        for _ in 0 .. ent_count
+        for ent in self.level_entities.drain(entity_list.len() ..)
         {
-            read_line(&mut fp, &mut line)?;
-            let mut it = line.split(',');
-            
-            let ty     : usize = it.next().expect("ent type").parse().expect("ent type");
-            let flags  : u16   = it.next().expect("ent flags").parse().expect("ent flags");
-            let x_fixed: i32   = it.next().expect("ent x").parse().expect("ent x");
-            let y_fixed: i32   = it.next().expect("ent y").parse().expect("ent y");
-            let z_fixed: i32   = it.next().expect("ent z").parse().expect("ent z");
-            let _unk1  : u32   = it.next().expect("ent unk1").parse().expect("ent unk1");
-            let _unk2  : u32   = it.next().expect("ent unk2").parse().expect("ent unk2");
-            let _unk3  : u32   = it.next().expect("ent unk3").parse().expect("ent unk3");
-
-            const COORD_SCALE_XZ: f64 = 1. / (1 << 20) as f64;
-            const COORD_SCALE_Y: f64 = 1. / (1 << 20) as f64;
-            ent_list.push(EntityRef {
-                ty: ty,
-                flags: flags,
-                // TODO: Use a 12.20 fixed point?
-                // - Note: X and Z have been switched, needed for the terrain to match
-                x: x_fixed as f64 * COORD_SCALE_XZ / 8. + 32. - 16.,
-                y: y_fixed as f64 * COORD_SCALE_Y  / 8.,
-                z: z_fixed as f64 * COORD_SCALE_XZ / 8. + 32. - 16.,
-                });
+            let _ = world.delete_entity(ent);
         }
-
-        Ok( (def_list, ent_list) )
+        debug!("Reloaded {} entities", entity_list.len());
+        Ok( () )
     }
 }
 
@@ -642,13 +966,21 @@ impl State for GameRoot
 {
     fn on_start(&mut self, world: &mut World)
     {
+        if !self.headless
+        {
+            world.write_resource::<::amethyst::assets::Loader>().add_source(pod_assets::SOURCE, pod_assets::PodSource::new(self.pods.clone()));
+        }
+
         // Load a random model (untextured)
         // DISABLED.
-        if true
+        if !self.headless
         {
-            //let model_path = datapath!(Game, Models, "LEAFSHIP.BIN");
-            let model_path = datapath!(Game, Models, "TENT2.BIN");
-            let (mesh, material) = self.load_model(world, model_path).unwrap();
+            // Loaded through the asset loader (in the background)
+            let mesh: a_renderer::MeshHandle = {
+                let loader = world.read_resource::<::amethyst::assets::Loader>();
+                loader.load_from(r"MODELS\TENT2.BIN", pod_assets::BinFormat, pod_assets::SOURCE, (), (), &world.read_resource())
+                };
+            let material = self.load_blue_material(world);
             world.create_entity()
                 .with(Transform::default())
                 .with(mesh)
@@ -657,78 +989,203 @@ impl State for GameRoot
                 ;
         }
 
-        // Load the "EGYPT" level from heightmap with its texture set
+        // Load the level's heightmap with its texture set
+        self.level_files = LevelFiles::from_level( &self.load_level_file(LEVEL_FILE).expect("Loading level descriptor") );
+        let files = self.level_files.clone();
         if true
         {
-            let (mat, tex_scales) = self.load_level_material(world, datapath!(Game, Data, "EGYPT.TEX"), datapath!(Game, Art, "EGYPT.ACT")).expect("Loading level tex");
-            let mesh = self.load_heightmap(world, datapath!(Game, Data, "EGYPT.RAW"), &tex_scales).expect("Loading level");
-            world.create_entity()
-                .with(Transform::default())
-                .with(mesh)
-                .with(mat)
-                .build()
-                ;
+            if self.headless
+            {
+                // Texture placement only matters for rendering, so use a single placeholder texture
+                let textures = vec![ terrain::AtlasRect { x: 0, y: 0, dim: 1 } ];
+                let terrain = self.load_heightmap(datapath!(Game, Data, &files.heightmap), textures).expect("Loading level");
+                world.add_resource(terrain);
+            }
+            else
+            {
+                let (mat, tex_scales) = self.load_level_material(world, datapath!(Game, Data, &files.texture_list), datapath!(Game, Art, &files.palette)).expect("Loading level tex");
+                let terrain = self.load_heightmap(datapath!(Game, Data, &files.heightmap), tex_scales).expect("Loading level");
+                let chunks: Vec<_> = {
+                    let loader = world.read_resource::<::amethyst::assets::Loader>();
+                    let mesh_storage = world.read_resource::<::amethyst::assets::AssetStorage<a_renderer::Mesh>>();
+                    let mut chunks = Vec::new();
+                    for cz in 0 .. terrain.chunk_count()
+                    {
+                        for cx in 0 .. terrain.chunk_count()
+                        {
+                            chunks.push( terrain_lod::TerrainChunkRef::build(&loader, &mesh_storage, &terrain, cx, cz, terrain::ChunkLod::full_detail()) );
+                        }
+                    }
+                    chunks
+                    };
+                world.register::<terrain_lod::TerrainChunkRef>();
+                for chunk in chunks
+                {
+                    world.create_entity()
+                        .with(Transform::default())
+                        .with(chunk.mesh.clone())
+                        .with(mat.clone())
+                        .with(chunk)
+                        .build()
+                        ;
+                }
+                world.add_resource(terrain);
+            }
         }
 
         // Load entities from the level entity file
         if true
         {
-            let (entity_types, entity_list) = self.load_entities_file(datapath!(Game, Data, "EGYPT.DEF")).expect("Loading level entities");
+            let (entity_types, entity_list) = self.load_entities_file(datapath!(Game, Data, &files.entities)).expect("Loading level entities");
 
             // - Load models for all entity types (and metadata?)
-            let mut model_mats = Vec::new();
-            for e in &entity_types
-            {
-                debug!("Load {:?} '{}'", e.model_a, e.description);
-                let model_path = datapath!(Game, Models, &e.model_a);
-                model_mats.push( self.load_model(world, model_path).unwrap() );
-            }
+            let (model_mats, destroyed_meshes) = self.load_entity_models(world, &entity_types);
+            entity_state::register(world);
+            let powerup_assets = if self.headless {
+                    powerups::PowerUpAssets { model: None }
+                }
+                else {
+                    let powerup_mat = self.load_blue_material(world);
+                    powerups::create_assets(world, powerup_mat)
+                };
+            powerups::initialise(world, DROP_SEED, powerup_assets);
             // - Place instances of those models into the world.
+            let units = world.read_resource::<terrain::Terrain>().units();
             for e in &entity_list//[..10]
             {
-                debug!("@{:7.3},{:7.3},{:9.3} #{}", e.x, e.y, e.z, e.ty);
+                let pos = e.render_position(&units);
+                debug!("@{:7.3},{:7.3},{:9.3} #{}", pos[0], pos[1], pos[2], e.ty);
 
+                let ent = world.create_entity().build();
+                place_entity(world, ent, e, &entity_types[e.ty], model_mats.get(e.ty).cloned().and_then(|v| v), destroyed_meshes.get(e.ty).cloned().and_then(|v| v));
+                self.level_entities.push(ent);
+            }
+        }
 
-                //let model_path = datapath!(Game, Models, &entity_types[e.ty].model_a);
-                //let (mesh, mat) = self.load_model(world, model_path).unwrap();
-                let (mesh, mat) = model_mats[e.ty].clone();
+        // Player ship
+        {
+            let model = if self.headless {
+                    None
+                }
+                else {
+                    match self.load_model(world, datapath!(Game, Models, PLAYER_MODEL))
+                    {
+                    Ok(v) => Some(v),
+                    Err(e) => {
+                        warn!("Unable to load player model {:?}: {}", PLAYER_MODEL, e);
+                        None
+                        },
+                    }
+                };
+            player::initialise(world, model);
+        }
 
-                world.create_entity()
-                    //.with(Transform::new())
-                    .with(Transform(Matrix4::from_translation([e.x as f32, e.y as f32, e.z as f32].into())))
-                    .with(mesh)
-                    .with(mat)
-                    .build()
-                    ;
-            }
-            ::std::mem::forget(model_mats);
+        if self.headless
+        {
+            world.add_resource(camera::CameraMode::Chase);
+        }
+        else
+        {
+            initialise_lights(world);
+            camera::initialise(world);
+            world.register::<sky::Sky>();
+            self.load_sky(world);
+            self.watcher = self.pods.overrides.as_ref().map(|dir| hot_reload::OverrideWatcher::new(dir));
         }
-            
-        initialise_lights(world);
-        initialise_camera(world);
+        world.add_resource(controls::Paused::default());
+    }
+    fn on_stop(&mut self, _: &mut World)
+    {
+        // Level unload
+        self.assets.clear();
     }
+
     fn handle_event(&mut self, _: &mut World, event: Event) -> Trans
     {
-        macro_rules! key_input
-        {
-            ($keycode:ident) => (::amethyst::renderer::WindowEvent::KeyboardInput {
-                    input: ::amethyst::renderer::KeyboardInput { virtual_keycode: Some(::amethyst::renderer::VirtualKeyCode::$keycode), .. },
-                    ..
-                    });
-        }
         match event
         {
             Event::WindowEvent { event, .. } => match event {
                 a_renderer::WindowEvent::Closed => Trans::Quit,
-                key_input!(Escape) => Trans::Quit,
                 _ => Trans::None,
             },
             _ => Trans::None,
         }
     }
+    fn update(&mut self, world: &mut World) -> Trans
+    {
+        let changed = match self.watcher
+            {
+            Some(ref mut w) => w.poll(),
+            None => Vec::new(),
+            };
+        for (folder, name) in changed
+        {
+            if let Err(e) = self.reload_file(world, folder, &name) {
+                error!("Unable to reload {:?} {}: {}", folder, name, e);
+            }
+        }
+
+        // Actions are checked here (instead of in `handle_event`), as the input handler only sees events once the
+        // dispatcher has run.
+        let input = world.read_resource::<::amethyst::input::InputHandler<String,String>>();
+        if self.presses.pressed(&input, controls::ACTION_QUIT) {
+            return Trans::Quit;
+        }
+        if self.presses.pressed(&input, controls::ACTION_PAUSE) {
+            let mut paused = world.write_resource::<controls::Paused>();
+            paused.0 = !paused.0;
+            debug!("Paused: {}", paused.0);
+        }
+        Trans::None
+    }
 }
 
 
+/// Set the components of a level entity from its placement and type (replacing any that were already there)
+fn place_entity(world: &mut World, ent: ecs::Entity, e: &EntityRef, def: &EntityDef,
+    model: Option<(a_renderer::MeshHandle, a_renderer::Material)>, destroyed_mesh: Option<a_renderer::MeshHandle>)
+{
+    let units = world.read_resource::<terrain::Terrain>().units();
+    let pos = e.render_position(&units);
+    world.write::<Transform>().insert(ent, Transform(Matrix4::from_translation(pos.into())));
+    world.write::<entity_state::EntityType>().insert(ent, entity_state::EntityType(e.ty));
+    world.write::<entity_state::PlacementFlags>().insert(ent, entity_state::PlacementFlags(e.flags));
+    match model
+    {
+    Some((mesh, mat)) => {
+        world.write::<a_renderer::MeshHandle>().insert(ent, mesh);
+        world.write::<a_renderer::Material>().insert(ent, mat);
+        },
+    None => {
+        world.write::<a_renderer::MeshHandle>().remove(ent);
+        world.write::<a_renderer::Material>().remove(ent);
+        },
+    }
+    if def.hit_points > 0
+    {
+        world.write::<entity_state::Health>().insert(ent, entity_state::Health::new(def.hit_points));
+        world.write::<powerups::DropTable>().insert(ent, powerups::DropTable { drops: def.drops, rolled: false });
+        match destroyed_mesh
+        {
+        Some(m) => { world.write::<entity_state::Destructible>().insert(ent, entity_state::Destructible { destroyed_mesh: m, destroyed: false }); },
+        None => { world.write::<entity_state::Destructible>().remove(ent); },
+        }
+    }
+    else
+    {
+        world.write::<entity_state::Health>().remove(ent);
+        world.write::<powerups::DropTable>().remove(ent);
+        world.write::<entity_state::Destructible>().remove(ent);
+    }
+}
+
+/// Hand decoded mesh data to the asset loader
+fn upload_mesh(world: &World, data: a_renderer::MeshData) -> ::amethyst::assets::Handle<a_renderer::Mesh>
+{
+    let loader = world.read_resource::<::amethyst::assets::Loader>();
+    loader.load_from_data(data, (), &world.read_resource())
+}
+
 /// This function adds an ambient light and a point light to the world.
 fn initialise_lights(world: &mut World)
 {
@@ -754,131 +1211,177 @@ fn initialise_lights(world: &mut World)
 }
 
 
-/// This function initialises a camera and adds it to the world.
-fn initialise_camera(world: &mut World) {
-    let transform = CameraMoveSystem::new().get_matrix();
-    world
-        .create_entity()
-        .with(a_renderer::Camera::from(a_renderer::Projection::perspective(1.3, Deg(60.0))))
-        .with(Transform(transform.into()))
-        .build();
-}
-
-struct CameraMoveSystem
+#[cfg(test)]
+mod tests
 {
-    x: f32,
-    y: f32,
-    z: f32,
-    tilt_deg: f32,
-    angle_deg: f32,
-}
-impl CameraMoveSystem
-{
-    fn new() -> CameraMoveSystem
-    {
-        CameraMoveSystem {
-            x: 0.,
-            y: 2.0,
-            z: 16.,
-            tilt_deg: 0.,
-            angle_deg: 0.,
-            }
-    }
+    use super::*;
 
-    fn get_matrix(&self) -> amethyst::core::cgmath::Matrix4<f32>
+    /// Largest vertical distance between a level entity and the terrain below it
+    ///
+    /// A consistency check of the unit conversions in `world_units`, as entities are placed on the ground.
+    fn max_entity_height_error(root: &mut GameRoot) -> f32
     {
-        Matrix4::from_scale(1.)
-            * Matrix4::from_translation([self.x, self.y, self.z].into())
-            * Matrix4::from_angle_y(Deg(self.angle_deg))
-            * Matrix4::from_angle_x(Deg(self.tilt_deg))
+        let files = LevelFiles::from_level( &root.load_level_file(LEVEL_FILE).unwrap() );
+        let textures = vec![ terrain::AtlasRect { x: 0, y: 0, dim: 1 } ];
+        let terrain = root.load_heightmap(datapath!(Game, Data, &files.heightmap), textures).unwrap();
+        let (_, entities) = root.load_entities_file(datapath!(Game, Data, &files.entities)).unwrap();
+        let units = terrain.units();
+        entities.iter()
+            .map(|e| {
+                let pos = e.render_position(&units);
+                (pos[1] - terrain.height_at(pos)).abs()
+                })
+            .fold(0., f32::max)
     }
 
-    fn shift(&mut self, angle: f32, step: f32)
+    #[test]
+    fn sky_clear_colour()
     {
-        self.x += angle.to_radians().sin() * step;
-        self.z += angle.to_radians().cos() * step;
+        let mut root = GameRoot::new(PodFiles::synthetic(), true);
+        let files = LevelFiles::from_level( &root.load_level_file(LEVEL_FILE).unwrap() );
+        let pixels = root.pods.file_data(datapath!(Game, Art, &files.sky_texture)).unwrap().into_owned();
+        let palette = root.pods.file_data(datapath!(Game, Art, &files.sky_palette)).unwrap().into_owned();
+        let dim = 64;
+        assert_eq!(pixels.len(), dim * dim);
+        let mut sum = [0.; 3];
+        for &p in &pixels[(dim - 1) * dim ..]
+        {
+            for (s, &v) in sum.iter_mut().zip(&palette[p as usize * 3 ..][..3]) {
+                *s += v as f32 / 255. / dim as f32;
+            }
+        }
+        let Rgba(r, g, b, a) = root.sky_clear_colour(LEVEL_FILE).unwrap();
+        for &(v, e) in &[(r, sum[0]), (g, sum[1]), (b, sum[2]), (a, 1.)] {
+            assert!((v - e).abs() < 1e-4, "{:?} != {:?}", (r, g, b, a), sum);
+        }
     }
-}
-impl<'s> ecs::System<'s> for CameraMoveSystem
-{
-    type SystemData = (
-        ecs::ReadStorage<'s, a_renderer::Camera>,
-        ecs::WriteStorage<'s, Transform>,
-        ecs::Fetch<'s, ::amethyst::input::InputHandler<String,String>>,
-        );
-    fn run(&mut self, (cam, mut transform, input): Self::SystemData)
+
+    /// Ten seconds of the synthetic level, through the same path as `--headless`
+    #[test]
+    fn headless_synthetic()
     {
-        let (_c, transform) = ecs::Join::join((&cam, &mut transform)).into_iter().next().unwrap();
+        use amethyst::ecs::Join;
+        let ship_state = |world: &World| {
+            let ships = world.read::<player::PlayerShip>();
+            let ships: Vec<_> = ships.join().map(|s| s.state).collect();
+            assert_eq!(ships.len(), 1);
+            ships[0]
+            };
+        let world = run_headless(PodFiles::synthetic(), 600);
+        let ship = ship_state(&world);
+        // Flying straight at the minimum speed (accelerating from a standstill), towards -Z
+        assert!(ship.position[2] < -4. && ship.position[2] > -5., "{:?}", ship);
+        assert_eq!(ship.speed, flight::FlightParams::default().min_speed);
+        assert!(ship.position[1] > world.read_resource::<terrain::Terrain>().height_at(ship.position), "{:?}", ship);
+        // Every destructible entity of the synthetic level is loaded, and none were hit
+        let health = world.read::<entity_state::Health>();
+        assert_eq!(health.join().count(), 4);
+        assert!(health.join().all(|h| !h.is_dead()));
+
+        // Runs are reproducible
+        assert_eq!(ship_state(&run_headless(PodFiles::synthetic(), 600)), ship);
+    }
 
-        let mut update = false;
-        for k in input.keys_that_are_down()
+    /// The synthetic level's textures are packed into the atlas without overlapping, each coloured by its own `.ACT`
+    /// if it has one and by the level palette otherwise
+    #[test]
+    fn level_atlas()
+    {
+        let mut root = GameRoot::new(PodFiles::synthetic(), true);
+        let files = LevelFiles::from_level( &root.load_level_file(LEVEL_FILE).unwrap() );
+        let mut world = World::new();
+        world.add_resource(::std::sync::Arc::new(::amethyst::core::rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap()));
+        let (width, height, rgba, rects) = root.load_level_atlas(&world, datapath!(Game, Data, &files.texture_list), datapath!(Game, Art, &files.palette)).unwrap();
+        let names = root.load_texture_list(datapath!(Game, Data, &files.texture_list)).unwrap();
+        assert_eq!(names.len(), datafile::synth::TEXTURE_COUNT);
+        assert_eq!(rects.len(), names.len());
+        assert_eq!(rgba.len(), width * height * 4);
+
+        let file = |name: &str| root.pods.file_data(datapath!(Game, Art, name)).map(|v| v.into_owned());
+        let level_palette = file(&files.palette).unwrap();
+        let mut own_palettes = 0;
+        for (i, (name, r)) in names.iter().zip(&rects).enumerate()
         {
-            const SPEED: f32 = 0.025;
-            const VSPEED: f32 = 0.01;
-            match k
+            let pixels = file(name).unwrap();
+            assert_eq!(r.dim * r.dim, pixels.len(), "{} is {} bytes, placed as {:?}", name, pixels.len(), r);
+            assert!(r.x + r.dim <= width && r.y + r.dim <= height, "{} placed outside the atlas at {:?}", name, r);
+            for o in &rects[i + 1 ..] {
+                assert!(r.x + r.dim <= o.x || o.x + o.dim <= r.x || r.y + r.dim <= o.y || o.y + o.dim <= r.y, "{:?} overlaps {:?}", r, o);
+            }
+
+            let palette = match file(&format!("{}ACT", &name[..name.len() - 3]))
+                {
+                Ok(v) => { own_palettes += 1; v },
+                Err(_) => level_palette.clone(),
+                };
+            for (j, &p) in pixels.iter().enumerate()
             {
-            ::amethyst::renderer::VirtualKeyCode::Left => {
-                self.angle_deg += 1.;
-                if self.angle_deg <= -180. {
-                    self.angle_deg += 360.;
-                }
-                update = true;
-                },
-            ::amethyst::renderer::VirtualKeyCode::Right => {
-                self.angle_deg -= 1.;
-                if self.angle_deg <= -180. {
-                    self.angle_deg -= 360.;
-                }
-                update = true;
-                },
-            ::amethyst::renderer::VirtualKeyCode::Down => {
-                self.tilt_deg -= 1.;
-                if self.tilt_deg <= -90. {
-                    self.tilt_deg = -90.;
-                }
-                update = true;
-                },
-            ::amethyst::renderer::VirtualKeyCode::Up => {
-                self.tilt_deg += 1.;
-                if self.tilt_deg >= 90. {
-                    self.tilt_deg = 90.;
-                }
-                update = true;
-                },
-            ::amethyst::renderer::VirtualKeyCode::W => {
-                let a = self.angle_deg;
-                self.shift(a, -SPEED);
-                update = true;
-                },
-            ::amethyst::renderer::VirtualKeyCode::S => {
-                let a = self.angle_deg - 180.;
-                self.shift(a, -SPEED);
-                update = true;
-                },
-            ::amethyst::renderer::VirtualKeyCode::A => {
-                let a = self.angle_deg - 90.;
-                self.shift(a, SPEED);
-                update = true;
-                },
-            ::amethyst::renderer::VirtualKeyCode::D => {
-                let a = self.angle_deg + 90.;
-                self.shift(a, SPEED);
-                update = true;
-                },
-            ::amethyst::renderer::VirtualKeyCode::R => {
-                self.y += VSPEED;
-                update = true;
-                },
-            ::amethyst::renderer::VirtualKeyCode::F => {
-                self.y -= VSPEED;
-                update = true;
-                },
-            _ => {},
+                let ofs = ((r.y + j / r.dim) * width + r.x + j % r.dim) * 4;
+                let c = &palette[p as usize * 3 ..][..3];
+                assert_eq!(&rgba[ofs .. ofs + 4], &[c[0], c[1], c[2], 255][..], "{} pixel {}", name, j);
             }
         }
-        if update
+        // Both palette cases are covered
+        assert!(own_palettes > 0 && own_palettes < names.len());
+    }
+
+    #[test]
+    fn modification_times()
+    {
+        let dir = ::std::env::temp_dir().join(format!("fury3clone-modified-{}", ::std::process::id()));
+        ::std::fs::create_dir_all(dir.join("MODELS")).unwrap();
+        ::std::fs::write(dir.join("MODELS").join("TENT2.BIN"), b"").unwrap();
+        let pods = PodFiles { overrides: Some(dir.clone()), ..PodFiles::synthetic() };
+
+        // Loose files have their own time, files only in the (in-memory) archives have none
+        let now = ::std::time::SystemTime::now().duration_since(::std::time::UNIX_EPOCH).unwrap().as_secs();
+        let loose = pods.modified_by_name(r"MODELS\TENT2.BIN").unwrap();
+        assert!(loose + 60 > now && loose <= now, "{} vs {}", loose, now);
+        assert_eq!(pods.modified_by_name(r"models\synpyr.bin").unwrap(), 0);
+        assert_eq!(pods.modified_by_name(r"MODELS\MISSING.BIN").unwrap_err().kind(), ::std::io::ErrorKind::NotFound);
+        ::std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// The synthetic entities sit on heightmap pixels, so each must be placed at that pixel's height as read straight
+    /// from the heightmap (this only checks the conversion is applied consistently, the scale of the `.DEF` Y itself
+    /// is checked against the retail data by `stock_entities_on_terrain`)
+    #[test]
+    fn entities_on_terrain()
+    {
+        let mut root = GameRoot::new(PodFiles::synthetic(), true);
+        let files = LevelFiles::from_level( &root.load_level_file(LEVEL_FILE).unwrap() );
+        let heights = root.pods.file_data(datapath!(Game, Data, &files.heightmap)).unwrap().into_owned();
+        let dim = (heights.len() as f64).sqrt() as usize;
+        let def = datafile::EntityFile::from_file(&root.pods.file_data(datapath!(Game, Data, &files.entities)).unwrap()[..]).unwrap();
+        let (_, entities) = root.load_entities_file(datapath!(Game, Data, &files.entities)).unwrap();
+        let units = world_units::WorldUnits::new(dim);
+
+        assert_eq!(entities.len(), def.placements.len());
+        for (e, p) in entities.iter().zip(&def.placements)
         {
-            transform.0 = self.get_matrix();
+            // Whole cells, offset by the map size
+            let x = ((p.x.to_bits() >> 20) + dim as i32) as usize;
+            let z = ((p.z.to_bits() >> 20) + dim as i32) as usize;
+            // The map is centred on the origin, with 8 cells and 256 height steps per render unit
+            let centre = (dim / 2) as f32;
+            let expected = [ (x as f32 - centre) / 8., heights[z * dim + x] as f32 / 256., (z as f32 - centre) / 8. ];
+            let pos = e.render_position(&units);
+            for i in 0 .. 3 {
+                assert!((pos[i] - expected[i]).abs() < 1. / 256., "Entity at {},{} placed at {:?}, expected {:?}", x, z, pos, expected);
+            }
         }
     }
-}
\ No newline at end of file
+
+    /// Entities in the retail level are placed by hand, allow them to be up to a cell's width from the ground (needs
+    /// `FURY3_SYSTEM`)
+    #[test]
+    #[ignore]
+    fn stock_entities_on_terrain()
+    {
+        assert!(::std::env::var_os(SYSTEM_DIR_VAR).is_some(), "{} must point at the game's data to run this test", SYSTEM_DIR_VAR);
+        let mut root = GameRoot::new(PodFiles::open(system_dir()).unwrap(), true);
+        let error = max_entity_height_error(&mut root);
+        let tolerance = world_units::WorldUnits::new(1).cell_size();
+        assert!(error < tolerance, "Entities are up to {} off the terrain (tolerance {})", error, tolerance);
+    }
+}
@@ -0,0 +1,126 @@
+//!
+//! Player ship entity and flight control
+//!
+use amethyst::ecs;
+use amethyst::ecs::Join;
+use amethyst::core::transform::Transform;
+use amethyst::renderer as a_renderer;
+
+use camera::CameraMode;
+use controls;
+use flight;
+use terrain;
+
+/// Most simulation steps run in one frame (any further time is dropped, so a long stall doesn't snowball)
+const MAX_STEPS_PER_FRAME: u32 = 10;
+
+pub struct PlayerShip
+{
+    /// State after the most recent simulation step
+    pub state: flight::ShipState,
+    /// State before the most recent step
+    pub prev_state: flight::ShipState,
+    /// State as rendered (interpolated between `prev_state` and `state`)
+    pub render_state: flight::ShipState,
+    pub params: flight::FlightParams,
+}
+impl PlayerShip
+{
+    pub fn new(state: flight::ShipState) -> PlayerShip
+    {
+        PlayerShip {
+            state: state,
+            prev_state: state,
+            render_state: state,
+            params: flight::FlightParams::default(),
+        }
+    }
+}
+impl ecs::Component for PlayerShip
+{
+    type Storage = ecs::HashMapStorage<Self>;
+}
+
+/// Reads the controls and advances the ship's flight model in fixed steps, then interpolates for rendering
+#[derive(Default)]
+pub struct FlightSystem
+{
+    /// Time not yet simulated
+    accumulator: f32,
+}
+impl<'s> ecs::System<'s> for FlightSystem
+{
+    type SystemData = (
+        ecs::Fetch<'s, ::amethyst::core::timing::Time>,
+        ecs::Fetch<'s, ::amethyst::input::InputHandler<String,String>>,
+        ecs::Fetch<'s, terrain::Terrain>,
+        ecs::Fetch<'s, CameraMode>,
+        ecs::Fetch<'s, controls::Paused>,
+        ecs::WriteStorage<'s, PlayerShip>,
+        ecs::WriteStorage<'s, Transform>,
+        );
+    fn run(&mut self, (time, input, terrain, camera_mode, paused, mut ships, mut transforms): Self::SystemData)
+    {
+        if paused.0 {
+            return ;
+        }
+
+        // The free and orbit cameras take over the controls, leaving the ship flying straight
+        let controls = if camera_mode.controls_ship() {
+                flight::FlightControls {
+                    throttle: controls::axis(&input, controls::AXIS_THROTTLE),
+                    pitch: -controls::axis(&input, controls::AXIS_PITCH),
+                    yaw: controls::axis(&input, controls::AXIS_YAW),
+                    roll: controls::axis(&input, controls::AXIS_ROLL),
+                }
+            }
+            else {
+                flight::FlightControls::default()
+            };
+
+        self.accumulator += time.delta_seconds();
+        let mut steps = 0;
+        while self.accumulator >= flight::FIXED_DT
+        {
+            self.accumulator -= flight::FIXED_DT;
+            steps += 1;
+        }
+        if steps > MAX_STEPS_PER_FRAME {
+            warn!("Flight simulation falling behind, dropping {} steps", steps - MAX_STEPS_PER_FRAME);
+            steps = MAX_STEPS_PER_FRAME;
+        }
+        // Fraction of a step that the rendered state lags behind the simulation
+        let alpha = self.accumulator / flight::FIXED_DT;
+
+        for (ship, transform) in (&mut ships, &mut transforms).join()
+        {
+            for _ in 0 .. steps {
+                ship.prev_state = ship.state;
+                ship.state = flight::step(&ship.state, &controls, &ship.params, |p| terrain.height_at(p));
+            }
+            ship.render_state = flight::lerp(&ship.prev_state, &ship.state, alpha);
+            transform.0 = ship.render_state.matrix();
+        }
+    }
+}
+
+/// Create the player ship above the centre of the map
+pub fn initialise(world: &mut ::amethyst::prelude::World, model: Option<(a_renderer::MeshHandle, a_renderer::Material)>)
+{
+    world.register::<PlayerShip>();
+    let state = {
+        let terrain = world.read_resource::<terrain::Terrain>();
+        let centre = [0., 0., 0.];
+        flight::ShipState::new([0., terrain.height_at(centre) + 1., 0.], 0.)
+        };
+    let mut builder = world.create_entity()
+        .with(Transform(state.matrix()))
+        .with(PlayerShip::new(state))
+        .with(::powerups::Collector::default())
+        ;
+    if let Some((mesh, mat)) = model
+    {
+        builder = builder.with(mesh).with(mat);
+    }
+    builder.build();
+}
@@ -0,0 +1,223 @@
+//!
+//! Amethyst asset source and formats for the game data
+//!
+//! `PodSource` serves files from the POD archives by their full name (e.g. `MODELS\TENT2.BIN`), and is registered
+//! with the loader as `SOURCE`. Assets are then loaded with e.g.
+//! `loader.load_from(r"MODELS\TENT2.BIN", BinFormat, SOURCE, (), (), &storage)`, getting the loader's background
+//! loading, handle deduplication and progress tracking.
+use std::sync::Arc;
+use amethyst::assets::{self, Format, FormatValue, SimpleFormat, Source};
+use amethyst::renderer as a_renderer;
+
+use datafile;
+use world_units::WorldUnits;
+use super::PodFiles;
+
+/// Name of the POD archive source in the loader
+pub const SOURCE: &str = "pod";
+
+/// Asset source reading from `STARTUP.POD` and `FURY3.POD`
+pub struct PodSource
+{
+    pods: Arc<PodFiles>,
+}
+impl PodSource
+{
+    pub(crate) fn new(pods: Arc<PodFiles>) -> PodSource
+    {
+        PodSource {
+            pods: pods,
+        }
+    }
+}
+impl Source for PodSource
+{
+    fn modified(&self, path: &str) -> Result<u64, assets::Error>
+    {
+        match self.pods.modified_by_name(path)
+        {
+        Ok(v) => Ok(v),
+        Err(e) => Err(format!("{}: {}", path, e).into()),
+        }
+    }
+
+    fn load(&self, path: &str) -> Result<Vec<u8>, assets::Error>
+    {
+        match self.pods.file_data_by_name(path)
+        {
+        Ok(v) => Ok(v.into_owned()),
+        Err(e) => Err(format!("{}: {}", path, e).into()),
+        }
+    }
+}
+
+/// `.BIN` model format
+#[derive(Clone)]
+pub struct BinFormat;
+impl SimpleFormat<a_renderer::Mesh> for BinFormat
+{
+    const NAME: &'static str = "FURY3_BIN";
+    type Options = ();
+
+    fn import(&self, bytes: Vec<u8>, _: ()) -> Result<a_renderer::MeshData, assets::Error>
+    {
+        match datafile::Model::from_bin_file(&bytes[..])
+        {
+        Ok(m) => Ok(model_mesh(&m)),
+        Err(e) => Err(e.to_string().into()),
+        }
+    }
+}
+
+/// Paletted `.RAW` texture format, coloured by the `.ACT` palette with the same name
+///
+/// The options give a fallback palette (e.g. the level's `ART\EGYPTSKY.ACT`) for textures without their own.
+// NOTE: Level textures are packed into an atlas instead, so this is only for standalone textures (the sky)
+#[derive(Clone)]
+pub struct RawFormat;
+impl Format<a_renderer::Texture> for RawFormat
+{
+    const NAME: &'static str = "FURY3_RAW";
+    type Options = Option<String>;
+
+    fn import(&self, name: String, source: Arc<Source>, options: Option<String>, _create_reload: bool)
+        -> Result<FormatValue<a_renderer::Texture>, assets::Error>
+    {
+        let pixels = source.load(&name)?;
+        let act_name = match name.rfind('.')
+            {
+            Some(p) => format!("{}.ACT", &name[..p]),
+            None => format!("{}.ACT", name),
+            };
+        // Only a missing palette falls back, one that can't be read is an error
+        let palette = if source.modified(&act_name).is_ok() {
+                source.load(&act_name)?
+            }
+            else {
+                match options
+                {
+                Some(ref fallback) => source.load(fallback)?,
+                None => return Err(format!("{}: no palette", name).into()),
+                }
+            };
+        let (dim, rgba) = paletted_rgba(&pixels, &palette)?;
+        Ok( FormatValue::data(rgba_texture(dim, dim, rgba, ::gfx_core::texture::WrapMode::Tile)) )
+    }
+}
+
+/// Mesh data for a model (flat shaded, with placeholder texture coordinates)
+pub fn model_mesh(m: &datafile::Model) -> a_renderer::MeshData
+{
+    let vertices_as_arrays: Vec<_> = m.faces.iter()
+        .flat_map(|v| v.v.iter().map(|&v| m.vertex_position(v)))
+        .map(|v| WorldUnits::model_position(v))
+        .collect();
+    debug!("vertices_as_arrays.len() = {}", vertices_as_arrays.len());
+    let normals: Vec<_> = m.faces.iter()
+        .flat_map(|v| {
+            let n = [v.normal[0].to_f32(), v.normal[1].to_f32(), v.normal[2].to_f32()];
+            v.v.iter().map(move |_| a_renderer::Separate::<a_renderer::Normal>::new(n))
+            })
+        .collect();
+    let tex_coords: Vec<_> = m.faces.iter()
+        .flat_map(|v| {
+            v.v.iter().map(move |_| a_renderer::Separate::<a_renderer::TexCoord>::new([0.1,0.1]))
+            })
+        .collect();
+
+    let m2: a_renderer::ComboMeshCreator = (
+        vertices_as_arrays.into_iter().map(|p| a_renderer::Separate::<a_renderer::Position>::new(p)).collect::<Vec<_>>(),
+        None,   // TODO: Colours
+        Some(tex_coords),   // Texture coords (needed)
+        Some(normals),   // TODO: Normals
+        None,   // TODO: Tangents
+        ).into();
+    m2.into()
+}
+
+/// Convert a square paletted texture to RGBA, returning its size and pixels
+pub fn paletted_rgba(pixels: &[u8], palette: &[u8]) -> Result<(usize, Vec<u8>), String>
+{
+    let dim = (pixels.len() as f64).sqrt() as usize;
+    if dim*dim != pixels.len() {
+        return Err(format!("Texture isn't square ({} bytes)", pixels.len()));
+    }
+    if palette.len() < 256*3 {
+        return Err(format!("Palette is {} bytes, not 768", palette.len()));
+    }
+    let rgba = pixels.iter()
+        .flat_map(|&b| {
+            let c = &palette[b as usize * 3 ..][..3];
+            vec![ c[0], c[1], c[2], 255 ]
+            })
+        .collect();
+    Ok( (dim, rgba) )
+}
+
+/// Texture data for RGBA pixels
+pub fn rgba_texture(width: usize, height: usize, rgba: Vec<u8>, wrap: ::gfx_core::texture::WrapMode) -> a_renderer::TextureData
+{
+    a_renderer::TextureData::U8(rgba,
+        a_renderer::TextureMetadata {
+            sampler: Some(::gfx_core::texture::SamplerInfo::new(
+                ::gfx_core::texture::FilterMethod::Bilinear,
+                wrap,
+                )),
+            mip_levels: None,
+            size: Some(( width as u16, height as u16 )),
+            dynamic: false,
+            format: Some(::gfx_core::format::SurfaceType::R8_G8_B8_A8),
+            channel: None,//Some(::gfx_core::format::ChannelType::Uint),
+            }
+        )
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// Import a texture from the synthetic archives, returning its size and pixels
+    fn import_raw(name: &str, fallback: Option<&str>) -> Result<((u16, u16), Vec<u8>), assets::Error>
+    {
+        let source: Arc<Source> = Arc::new(PodSource::new( Arc::new(PodFiles::synthetic()) ));
+        let value = RawFormat.import(name.to_owned(), source, fallback.map(|v| v.to_owned()), false)?;
+        match value.data
+        {
+        a_renderer::TextureData::U8(rgba, meta) => Ok( (meta.size.unwrap(), rgba) ),
+        _ => panic!("Unexpected texture data for {}", name),
+        }
+    }
+
+    /// Colour of the first pixel (the texture's base palette entry) from a synthetic palette
+    fn first_pixel(texture: &str, palette: &str) -> Vec<u8>
+    {
+        let pods = PodFiles::synthetic();
+        let index = pods.file_data_by_name(texture).unwrap()[0] as usize;
+        let act = pods.file_data_by_name(palette).unwrap();
+        vec![ act[index*3], act[index*3 + 1], act[index*3 + 2], 255 ]
+    }
+
+    #[test]
+    fn raw_own_palette()
+    {
+        // The same-named palette is used even if there's a fallback
+        let (size, rgba) = import_raw(r"ART\EGYPT0.RAW", Some(r"ART\EGYPT.ACT")).unwrap();
+        let dim = datafile::synth::TEXTURE_DIM;
+        assert_eq!(size, (dim as u16, dim as u16));
+        assert_eq!(rgba.len(), dim * dim * 4);
+        assert_eq!(&rgba[..4], &first_pixel(r"ART\EGYPT0.RAW", r"ART\EGYPT0.ACT")[..]);
+    }
+
+    #[test]
+    fn raw_fallback_palette()
+    {
+        let (size, rgba) = import_raw(r"ART\EGYPT1.RAW", Some(r"ART\EGYPT.ACT")).unwrap();
+        let dim = datafile::synth::TEXTURE_DIM / 2;
+        assert_eq!(size, (dim as u16, dim as u16));
+        assert_eq!(&rgba[..4], &first_pixel(r"ART\EGYPT1.RAW", r"ART\EGYPT.ACT")[..]);
+
+        assert!(import_raw(r"ART\EGYPT1.RAW", None).is_err());
+        assert!(import_raw(r"ART\EGYPT1.RAW", Some(r"ART\MISSING.ACT")).is_err());
+    }
+}
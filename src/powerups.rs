@@ -0,0 +1,344 @@
+//!
+//! Power-up drops (from the `.DEF` drop tables) and pickup
+//!
+//! TODO: Use the level's `.PUP` data for the power-up models and effects once it's decoded.
+use amethyst::ecs;
+use amethyst::ecs::Join;
+use amethyst::core::transform::Transform;
+use amethyst::core::cgmath::Matrix4;
+use amethyst::renderer as a_renderer;
+
+use controls::Paused;
+use entity_state::Health;
+
+/// Distance (render units) within which a power-up is collected
+const PICKUP_RADIUS: f32 = 0.5;
+
+/// Deterministic random number source for drops (xorshift64*), so a given seed always gives the same drops
+pub struct DropRng
+{
+    state: u64,
+}
+impl DropRng
+{
+    pub fn new(seed: u64) -> DropRng
+    {
+        // xorshift can't have a zero state
+        DropRng { state: if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed } }
+    }
+    pub fn next_u32(&mut self) -> u32
+    {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 32) as u32
+    }
+    /// Uniform value in `[0, 1)`
+    pub fn next_f32(&mut self) -> f32
+    {
+        (self.next_u32() >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+/// Possible drops for an entity, as (chance from 0 to 1, item ID)
+pub struct DropTable
+{
+    pub drops: [(f32, u8); 2],
+    /// Set once the drops have been rolled (so they're only rolled once)
+    pub rolled: bool,
+}
+impl ecs::Component for DropTable
+{
+    type Storage = ecs::VecStorage<Self>;
+}
+
+/// Roll each entry of a drop table, returning the items that dropped
+pub fn roll_drops(rng: &mut DropRng, drops: &[(f32, u8)]) -> Vec<u8>
+{
+    // Each (non-zero) entry is rolled independently, in table order
+    drops.iter()
+        .filter(|&&(chance, _)| chance > 0.)
+        .filter(|&&(chance, _)| rng.next_f32() < chance)
+        .map(|&(_, item)| item)
+        .collect()
+}
+
+/// A dropped power-up waiting to be collected
+pub struct PowerUp
+{
+    pub item: u8,
+}
+impl ecs::Component for PowerUp
+{
+    type Storage = ecs::VecStorage<Self>;
+}
+
+/// An entity that collects power-ups it comes close to (the player)
+#[derive(Default)]
+pub struct Collector
+{
+    /// Collected items, in pickup order
+    pub items: Vec<u8>,
+}
+impl ecs::Component for Collector
+{
+    type Storage = ecs::VecStorage<Self>;
+}
+
+/// Mesh and material used for spawned power-ups (`None` when running headless)
+pub struct PowerUpAssets
+{
+    pub model: Option<(a_renderer::MeshHandle, a_renderer::Material)>,
+}
+
+/// Create a placeholder power-up model (an octahedron)
+pub fn create_assets(world: &::amethyst::prelude::World, material: a_renderer::Material) -> PowerUpAssets
+{
+    const R: f32 = 0.1;
+    let points = [ [R,0.,0.], [0.,0.,R], [-R,0.,0.], [0.,0.,-R] ];
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    for i in 0 .. 4
+    {
+        let (a, b) = (points[i], points[(i + 1) % 4]);
+        for &(tip, tri) in &[ ([0., R, 0.], [a, b]), ([0., -R, 0.], [b, a]) ]
+        {
+            let n = [ (tri[0][0] + tri[1][0] + tip[0]) / 3., (tri[0][1] + tri[1][1] + tip[1]) / 3., (tri[0][2] + tri[1][2] + tip[2]) / 3. ];
+            for &p in &[tri[0], tri[1], tip]
+            {
+                positions.push( a_renderer::Separate::<a_renderer::Position>::new(p) );
+                normals.push( a_renderer::Separate::<a_renderer::Normal>::new(n) );
+            }
+        }
+    }
+    let tex_coords = positions.iter().map(|_| a_renderer::Separate::<a_renderer::TexCoord>::new([0.1, 0.1])).collect();
+
+    let loader = world.read_resource::<::amethyst::assets::Loader>();
+    let m2: a_renderer::ComboMeshCreator = (
+        positions,
+        None,   // Colours
+        Some(tex_coords),   // Texture coords (needed)
+        Some(normals),
+        None,   // Tangents
+        ).into();
+    let mesh = loader.load_from_data(m2.into(), (), &world.read_resource::<::amethyst::assets::AssetStorage<a_renderer::Mesh>>());
+    PowerUpAssets {
+        model: Some( (mesh, material) ),
+        }
+}
+
+/// Register the components, and add the RNG and power-up assets
+pub fn initialise(world: &mut ::amethyst::prelude::World, seed: u64, assets: PowerUpAssets)
+{
+    world.register::<DropTable>();
+    world.register::<PowerUp>();
+    world.register::<Collector>();
+    world.add_resource(DropRng::new(seed));
+    world.add_resource(assets);
+}
+
+/// Spawns power-ups when an entity with a drop table dies
+pub struct DropSystem;
+impl<'s> ecs::System<'s> for DropSystem
+{
+    type SystemData = (
+        ecs::Entities<'s>,
+        ecs::Fetch<'s, Paused>,
+        ecs::FetchMut<'s, DropRng>,
+        ecs::Fetch<'s, PowerUpAssets>,
+        ecs::ReadStorage<'s, Health>,
+        ecs::WriteStorage<'s, DropTable>,
+        ecs::WriteStorage<'s, Transform>,
+        ecs::WriteStorage<'s, PowerUp>,
+        ecs::WriteStorage<'s, a_renderer::MeshHandle>,
+        ecs::WriteStorage<'s, a_renderer::Material>,
+        );
+    fn run(&mut self, (entities, paused, mut rng, assets, health, mut tables, mut transforms, mut powerups, mut meshes, mut materials): Self::SystemData)
+    {
+        if paused.0 {
+            return ;
+        }
+        let mut spawns = Vec::new();
+        for (h, table, t) in (&health, &mut tables, &transforms).join()
+        {
+            if table.rolled || !h.is_dead() {
+                continue ;
+            }
+            table.rolled = true;
+            for item in roll_drops(&mut rng, &table.drops)
+            {
+                spawns.push( (item, [t.0.w.x, t.0.w.y, t.0.w.z]) );
+            }
+        }
+
+        for (i, (item, pos)) in spawns.into_iter().enumerate()
+        {
+            debug!("Dropping item {} at {:?}", item, pos);
+            // Spread multiple drops out slightly
+            let pos = [pos[0] + i as f32 * 0.1, pos[1], pos[2]];
+            let e = entities.create();
+            transforms.insert(e, Transform(Matrix4::from_translation(pos.into())));
+            powerups.insert(e, PowerUp { item: item });
+            if let Some((ref mesh, ref material)) = assets.model {
+                meshes.insert(e, mesh.clone());
+                materials.insert(e, material.clone());
+            }
+        }
+    }
+}
+
+/// Hands power-ups to any collector within `PICKUP_RADIUS`
+pub struct PickupSystem;
+impl<'s> ecs::System<'s> for PickupSystem
+{
+    type SystemData = (
+        ecs::Entities<'s>,
+        ecs::Fetch<'s, Paused>,
+        ecs::ReadStorage<'s, Transform>,
+        ecs::ReadStorage<'s, PowerUp>,
+        ecs::WriteStorage<'s, Collector>,
+        );
+    fn run(&mut self, (entities, paused, transforms, powerups, mut collectors): Self::SystemData)
+    {
+        if paused.0 {
+            return ;
+        }
+        for (collector, ct) in (&mut collectors, &transforms).join()
+        {
+            for (e, p, pt) in (&*entities, &powerups, &transforms).join()
+            {
+                let d = [pt.0.w.x - ct.0.w.x, pt.0.w.y - ct.0.w.y, pt.0.w.z - ct.0.w.z];
+                if d[0]*d[0] + d[1]*d[1] + d[2]*d[2] < PICKUP_RADIUS * PICKUP_RADIUS && entities.is_alive(e)
+                {
+                    debug!("Picked up item {}", p.item);
+                    collector.items.push(p.item);
+                    let _ = entities.delete(e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use amethyst::ecs::RunNow;
+    use amethyst::prelude::World;
+    use entity_state;
+    use flight::ShipState;
+    use player::PlayerShip;
+
+    #[test]
+    fn fixed_seed_drops()
+    {
+        let mut rng = DropRng::new(0x4675_7279_3300);
+        let rolls: Vec<_> = (0 .. 8).map(|_| roll_drops(&mut rng, &[(0.5, 3), (0.25, 7)])).collect();
+        assert_eq!(rolls, vec![ vec![], vec![], vec![], vec![3], vec![3, 7], vec![3, 7], vec![], vec![] ]);
+
+        // Empty slots don't use up a roll
+        let mut a = DropRng::new(1);
+        let mut b = DropRng::new(1);
+        assert_eq!(roll_drops(&mut a, &[(0., 5), (0.5, 3)]), roll_drops(&mut b, &[(0.5, 3)]));
+        assert_eq!(a.next_u32(), b.next_u32());
+    }
+
+    fn world() -> World
+    {
+        let mut world = World::new();
+        world.register::<Transform>();
+        world.register::<a_renderer::MeshHandle>();
+        world.register::<a_renderer::Material>();
+        world.register::<PlayerShip>();
+        world.add_resource(Paused::default());
+        entity_state::register(&mut world);
+        initialise(&mut world, 2, PowerUpAssets { model: None });
+        world
+    }
+    fn step(world: &mut World)
+    {
+        entity_state::CollisionSystem.run_now(&world.res);
+        entity_state::DestructionSystem.run_now(&world.res);
+        DropSystem.run_now(&world.res);
+        world.maintain();
+        PickupSystem.run_now(&world.res);
+        world.maintain();
+    }
+
+    /// Ramming an entity to death drops its items (rolled once), which the ship then collects
+    #[test]
+    fn destruction_drops()
+    {
+        let mut world = world();
+        let pos = [1., 2., 3.];
+        let target = world.create_entity()
+            .with(Transform(Matrix4::from_translation(pos.into())))
+            .with(entity_state::Health::new(entity_state::RAM_DAMAGE))
+            .with(DropTable { drops: [(0.5, 3), (0.5, 7)], rolled: false })
+            .build();
+        let ship = world.create_entity()
+            .with(Transform(Matrix4::from_translation(pos.into())))
+            .with(PlayerShip::new(ShipState::new(pos, 0.)))
+            .with(Collector::default())
+            .build();
+
+        for _ in 0 .. 3 {
+            step(&mut world);
+        }
+        assert!(world.read::<entity_state::Health>().get(target).unwrap().is_dead());
+        assert!(world.read::<DropTable>().get(target).unwrap().rolled);
+        // With seed 2 only the second entry drops
+        assert_eq!(world.read::<Collector>().get(ship).unwrap().items, vec![7]);
+        assert_eq!(world.read::<PowerUp>().join().count(), 0);
+    }
+
+    /// Nothing is damaged, destroyed, dropped or collected while paused, and everything carries on once unpaused
+    #[test]
+    fn paused()
+    {
+        let mut world = world();
+        let storage = ::amethyst::assets::AssetStorage::<a_renderer::Mesh>::new();
+        let (model_a, model_b) = (storage.allocate(), storage.allocate());
+        let pos = [1., 2., 3.];
+        // One entity being rammed, one already destroyed, and a power-up under the ship
+        let rammed = world.create_entity()
+            .with(Transform(Matrix4::from_translation(pos.into())))
+            .with(entity_state::Health::new(entity_state::RAM_DAMAGE * 2))
+            .build();
+        let destroyed = world.create_entity()
+            .with(Transform(Matrix4::from_translation([5., 2., 3.].into())))
+            .with(model_a.clone())
+            .with(entity_state::Health::new(0))
+            .with(entity_state::Destructible { destroyed_mesh: model_b.clone(), destroyed: false })
+            .with(DropTable { drops: [(1., 3), (0., 0)], rolled: false })
+            .build();
+        world.create_entity()
+            .with(Transform(Matrix4::from_translation(pos.into())))
+            .with(PowerUp { item: 5 })
+            .build();
+        let ship = world.create_entity()
+            .with(Transform(Matrix4::from_translation(pos.into())))
+            .with(PlayerShip::new(ShipState::new(pos, 0.)))
+            .with(Collector::default())
+            .build();
+
+        world.write_resource::<Paused>().0 = true;
+        for _ in 0 .. 3 {
+            step(&mut world);
+        }
+        assert_eq!(world.read::<entity_state::Health>().get(rammed).unwrap().current, entity_state::RAM_DAMAGE * 2);
+        assert!(!world.read::<entity_state::Destructible>().get(destroyed).unwrap().destroyed);
+        assert_eq!(world.read::<a_renderer::MeshHandle>().get(destroyed).unwrap().id(), model_a.id());
+        assert!(!world.read::<DropTable>().get(destroyed).unwrap().rolled);
+        assert_eq!(world.read::<PowerUp>().join().count(), 1);
+        assert!(world.read::<Collector>().get(ship).unwrap().items.is_empty());
+
+        world.write_resource::<Paused>().0 = false;
+        step(&mut world);
+        assert_eq!(world.read::<entity_state::Health>().get(rammed).unwrap().current, entity_state::RAM_DAMAGE);
+        assert_eq!(world.read::<a_renderer::MeshHandle>().get(destroyed).unwrap().id(), model_b.id());
+        assert!(world.read::<DropTable>().get(destroyed).unwrap().rolled);
+        assert_eq!(world.read::<Collector>().get(ship).unwrap().items, vec![5]);
+        assert_eq!(world.read::<PowerUp>().join().count(), 1);
+    }
+}
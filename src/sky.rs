@@ -0,0 +1,118 @@
+//!
+//! Sky cylinder, textured with the level's sky texture and kept centred on the camera
+//!
+use amethyst::ecs;
+use amethyst::ecs::Join;
+use amethyst::core::transform::Transform;
+use amethyst::core::cgmath::{Matrix4, Vector3};
+use amethyst::renderer as a_renderer;
+
+/// Radius of the cylinder, past the edge of the largest maps (but within the camera's far plane)
+const RADIUS: f32 = 200.;
+/// Height of the top of the cylinder above the camera
+const HEIGHT: f32 = 80.;
+/// Distance the cylinder extends below the camera (so there's no gap at the horizon)
+const DEPTH: f32 = 20.;
+const SEGMENTS: usize = 32;
+/// Number of times the texture wraps around the cylinder
+const TEXTURE_REPEAT: f32 = 4.;
+
+/// Marks the sky entity
+#[derive(Default)]
+pub struct Sky;
+impl ecs::Component for Sky
+{
+    type Storage = ecs::NullStorage<Self>;
+}
+
+/// Inward facing cylinder, with a cap at the top coloured by the top row of the texture
+pub fn cylinder_mesh() -> a_renderer::MeshData
+{
+    let mut positions = Vec::new();
+    let mut tex_coords = Vec::new();
+    let mut normals = Vec::new();
+    {
+        let mut vert = |p: [f32; 3], uv: [f32; 2], n: [f32; 3]| {
+            positions.push(a_renderer::Separate::<a_renderer::Position>::new(p));
+            tex_coords.push(a_renderer::Separate::<a_renderer::TexCoord>::new(uv));
+            normals.push(a_renderer::Separate::<a_renderer::Normal>::new(n));
+            };
+        let step = 2. * ::std::f32::consts::PI / SEGMENTS as f32;
+        let pos = |i: usize, y: f32| [RADIUS * (i as f32 * step).cos(), y, RADIUS * (i as f32 * step).sin()];
+        let normal = |i: usize| [-(i as f32 * step).cos(), 0., -(i as f32 * step).sin()];
+        let u = |i: usize| i as f32 / SEGMENTS as f32 * TEXTURE_REPEAT;
+        for i in 0 .. SEGMENTS
+        {
+            let j = i + 1;
+            // Side, wound to face the centre
+            vert(pos(i, -DEPTH), [u(i), 1.], normal(i));
+            vert(pos(j, -DEPTH), [u(j), 1.], normal(j));
+            vert(pos(i, HEIGHT), [u(i), 0.], normal(i));
+            vert(pos(j, -DEPTH), [u(j), 1.], normal(j));
+            vert(pos(j, HEIGHT), [u(j), 0.], normal(j));
+            vert(pos(i, HEIGHT), [u(i), 0.], normal(i));
+            // Cap
+            let mid = [(u(i) + u(j)) / 2., 0.];
+            vert([0., HEIGHT, 0.], mid, [0., -1., 0.]);
+            vert(pos(i, HEIGHT), mid, [0., -1., 0.]);
+            vert(pos(j, HEIGHT), mid, [0., -1., 0.]);
+        }
+    }
+
+    let m: a_renderer::ComboMeshCreator = (
+        positions,
+        None,   // Colours
+        Some(tex_coords),
+        Some(normals),
+        None,   // Tangents
+        ).into();
+    m.into()
+}
+
+/// Average colour of the bottom row of an RGBA texture (the part of the sky at the horizon)
+pub fn bottom_row_colour(dim: usize, rgba: &[u8]) -> [f32; 4]
+{
+    let mut sum = [0.; 4];
+    for px in rgba[(dim - 1) * dim * 4 ..].chunks(4)
+    {
+        for (s, &v) in sum.iter_mut().zip(px) {
+            *s += v as f32 / 255.;
+        }
+    }
+    [sum[0] / dim as f32, sum[1] / dim as f32, sum[2] / dim as f32, sum[3] / dim as f32]
+}
+
+/// Create the sky entity (`Sky` must already be registered, as `SkySystem` needs it even if there's no sky)
+pub fn initialise(world: &mut ::amethyst::prelude::World, mesh: a_renderer::MeshHandle, material: a_renderer::Material) -> ecs::Entity
+{
+    world
+        .create_entity()
+        .with(Transform::default())
+        .with(mesh)
+        .with(material)
+        .with(Sky)
+        .build()
+}
+
+/// Moves the sky with the camera (so it always appears at the same distance)
+pub struct SkySystem;
+impl<'s> ecs::System<'s> for SkySystem
+{
+    type SystemData = (
+        ecs::ReadStorage<'s, a_renderer::Camera>,
+        ecs::ReadStorage<'s, Sky>,
+        ecs::WriteStorage<'s, Transform>,
+        );
+    fn run(&mut self, (cameras, skies, mut transforms): Self::SystemData)
+    {
+        let eye = match (&cameras, &transforms).join().next()
+            {
+            Some((_, t)) => Vector3::new(t.0.w.x, t.0.w.y, t.0.w.z),
+            None => return,
+            };
+        for (_, t) in (&skies, &mut transforms).join()
+        {
+            t.0 = Matrix4::from_translation(eye);
+        }
+    }
+}
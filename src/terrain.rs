@@ -0,0 +1,652 @@
+//!
+//! Terrain mesh generation from the level heightmap (`.RAW`) and texture map (`.CLR`)
+//!
+//! This is purely CPU-side, `GameRoot::load_heightmap` and `terrain_lod::TerrainLodSystem` upload the generated chunks
+//! as meshes.
+//!
+//! Level of detail is done with geomipmapping: a chunk at level `n` samples every `2^n`th heightmap pixel. Cracks
+//! between chunks of different levels are avoided by triangulating the outer ring of each chunk separately, with the
+//! shared edge always using the coarser of the two steps (so both sides emit exactly the same edge vertices).
+use std::collections::HashMap;
+use world_units::WorldUnits;
+
+/// Number of cells along each side of a terrain chunk
+pub const CHUNK_SIZE: usize = 32;
+/// Coarsest level of detail (a step of `2^MAX_LOD` cells)
+pub const MAX_LOD: u8 = 4;
+/// Camera distances (render units) at which the next level of detail is used
+const LOD_DISTANCES: [f32; MAX_LOD as usize] = [ 8., 16., 32., 64. ];
+
+/// A square grid of bytes (used for both the `.RAW` heightmap and the `.CLR` texture map)
+pub struct ByteGrid
+{
+    dim: usize,
+    data: Vec<u8>,
+}
+impl ByteGrid
+{
+    pub fn from_reader<F: ::std::io::Read>(mut file: F) -> ::std::io::Result<ByteGrid>
+    {
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        let dim = (data.len() as f64).sqrt() as usize;
+        if dim * dim != data.len() || dim < 2 {
+            return Err(::std::io::Error::new(::std::io::ErrorKind::InvalidData, "Map file isn't a square"));
+        }
+        Ok(ByteGrid {
+            dim: dim,
+            data: data,
+            })
+    }
+
+    /// Number of pixels along each side
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+    /// Fetch a value, clamping the coordinates to the edge of the grid
+    pub fn get(&self, x: isize, z: isize) -> u8 {
+        let clamp = |v: isize| if v < 0 { 0 } else if v as usize >= self.dim { self.dim - 1 } else { v as usize };
+        self.data[clamp(z) * self.dim + clamp(x)]
+    }
+}
+
+/// A block of terrain with shared, indexed vertices
+pub struct TerrainChunk
+{
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub tex_coords: Vec<[f32; 2]>,
+    /// Triangle list indexing the above vertex arrays
+    pub indices: Vec<u32>,
+    /// Axis-aligned bounding box (min, max), for culling (see `bounds_in_frustum`)
+    pub bounds: ([f32; 3], [f32; 3]),
+}
+impl TerrainChunk
+{
+    /// Expand the index list into a flat list of vertices (Amethyst 0.6 meshes don't have an index buffer)
+    pub fn unindexed<'a, T: Copy>(&'a self, data: &'a [T]) -> impl Iterator<Item=T> + 'a
+    {
+        self.indices.iter().map(move |&i| data[i as usize])
+    }
+}
+
+/// Location of a level texture within the packed texture atlas
+#[derive(Copy,Clone,Debug)]
+pub struct AtlasRect
+{
+    pub x: usize,
+    pub y: usize,
+    pub dim: usize,
+}
+
+/// Level of detail for a chunk, and the levels of its neighbours (needed to stitch the edges)
+#[derive(Copy,Clone,Debug,PartialEq,Eq,Hash)]
+pub struct ChunkLod
+{
+    pub level: u8,
+    /// Levels of the neighbouring chunks, in order -Z, +X, +Z, -X (use `level` when there's no neighbour)
+    pub edges: [u8; 4],
+}
+impl ChunkLod
+{
+    pub fn full_detail() -> ChunkLod {
+        ChunkLod { level: 0, edges: [0; 4] }
+    }
+}
+
+/// Pick the level of detail to use for a chunk at the given distance from the camera
+pub fn lod_for_distance(dist: f32) -> u8
+{
+    LOD_DISTANCES.iter().filter(|&&d| d < dist).count() as u8
+}
+
+/// Check if an axis-aligned box (min, max) is at least partly inside the view frustum
+///
+/// `clip` is the combined projection and view matrix, column-major (as `cgmath::Matrix4` converts to). The frustum
+/// planes are taken from its rows, and the box is outside if its furthest corner along a plane's normal is behind it.
+pub fn bounds_in_frustum(clip: &[[f32; 4]; 4], bounds: &([f32; 3], [f32; 3])) -> bool
+{
+    let row = |i: usize| [clip[0][i], clip[1][i], clip[2][i], clip[3][i]];
+    let w = row(3);
+    for &(axis, sign) in &[ (0, 1.), (0, -1.), (1, 1.), (1, -1.), (2, 1.), (2, -1.) ]
+    {
+        let r = row(axis);
+        let p = [ w[0] + sign * r[0], w[1] + sign * r[1], w[2] + sign * r[2], w[3] + sign * r[3] ];
+        let corner = |i: usize| if p[i] >= 0. { bounds.1[i] } else { bounds.0[i] };
+        if p[0] * corner(0) + p[1] * corner(1) + p[2] * corner(2) + p[3] < 0. {
+            return false;
+        }
+    }
+    true
+}
+
+/// The loaded level terrain, from which chunk meshes are generated
+pub struct Terrain
+{
+    heights: ByteGrid,
+    colours: ByteGrid,
+    textures: Vec<AtlasRect>,
+    atlas_size: (usize, usize),
+    units: WorldUnits,
+}
+impl Terrain
+{
+    /// `textures` maps `.CLR` values to locations within the level texture atlas
+    pub fn new(heights: ByteGrid, colours: ByteGrid, textures: Vec<AtlasRect>) -> Result<Terrain, String>
+    {
+        if heights.dim() != colours.dim() {
+            return Err(format!("Heightmap and colour map size mismatch - {} != {}", heights.dim(), colours.dim()));
+        }
+        if textures.is_empty() {
+            return Err("No terrain textures".to_owned());
+        }
+        let atlas_size = (
+            textures.iter().map(|v| v.x+v.dim).max().unwrap(),
+            textures.iter().map(|v| v.y+v.dim).max().unwrap(),
+            );
+        Ok(Terrain {
+            units: WorldUnits::new(heights.dim()),
+            heights: heights,
+            colours: colours,
+            textures: textures,
+            atlas_size: atlas_size,
+            })
+    }
+
+    pub fn heights(&self) -> &ByteGrid {
+        &self.heights
+    }
+    pub fn units(&self) -> WorldUnits {
+        self.units
+    }
+
+    /// Render-space height of the terrain surface below the given position (bilinear between the heightmap pixels)
+    pub fn height_at(&self, pos: [f32; 3]) -> f32
+    {
+        let (fx, fz) = self.units.terrain_cell(pos);
+        let (x, z) = (fx.floor(), fz.floor());
+        let (tx, tz) = (fx - x, fz - z);
+        let (x, z) = (x as isize, z as isize);
+        let h = |x, z| self.heights.get(x, z) as f32;
+        let top = h(x, z) * (1. - tx) + h(x+1, z) * tx;
+        let bottom = h(x, z+1) * (1. - tx) + h(x+1, z+1) * tx;
+        (top * (1. - tz) + bottom * tz) * self.units.height_step()
+    }
+
+    /// Number of chunks along each side of the map
+    pub fn chunk_count(&self) -> usize
+    {
+        (self.cell_count() + CHUNK_SIZE - 1) / CHUNK_SIZE
+    }
+    fn cell_count(&self) -> usize {
+        self.heights.dim() - 1
+    }
+    /// Cell ranges covered by the given chunk
+    fn chunk_cells(&self, cx: usize, cz: usize) -> (::std::ops::Range<usize>, ::std::ops::Range<usize>)
+    {
+        let cells = self.cell_count();
+        (
+            cx * CHUNK_SIZE .. ::std::cmp::min(cells, (cx+1) * CHUNK_SIZE),
+            cz * CHUNK_SIZE .. ::std::cmp::min(cells, (cz+1) * CHUNK_SIZE),
+            )
+    }
+    /// Render-space centre of a chunk (at the average of its corner heights)
+    pub fn chunk_centre(&self, cx: usize, cz: usize) -> [f32; 3]
+    {
+        let (xr, zr) = self.chunk_cells(cx, cz);
+        let corners = [
+            self.vertex_position(xr.start, zr.start), self.vertex_position(xr.end, zr.start),
+            self.vertex_position(xr.start, zr.end  ), self.vertex_position(xr.end, zr.end  ),
+            ];
+        let mut rv = [0.; 3];
+        for c in &corners {
+            for i in 0 .. 3 {
+                rv[i] += c[i] / 4.;
+            }
+        }
+        rv
+    }
+
+    /// Reduce a level of detail so the chunk still has at least one interior grid line at that step
+    ///
+    /// Apply this before passing levels to neighbours, so both sides of an edge agree on the step.
+    pub fn clamp_lod(&self, cx: usize, cz: usize, level: u8) -> u8
+    {
+        let (xr, zr) = self.chunk_cells(cx, cz);
+        let n = ::std::cmp::min(xr.len(), zr.len());
+        let mut level = ::std::cmp::min(level, MAX_LOD);
+        while level > 0 && (1 << level) >= n {
+            level -= 1;
+        }
+        level
+    }
+
+    /// Generate the mesh for a chunk at the given level of detail
+    ///
+    /// The interior is a regular grid at the chunk's step, and the outer ring is stitched between the interior and an
+    /// edge sampled at the coarser of this chunk's and the neighbour's steps. Vertices are shared between triangles that
+    /// use the same texture block.
+    pub fn build_chunk(&self, cx: usize, cz: usize, lod: ChunkLod) -> TerrainChunk
+    {
+        let (xr, zr) = self.chunk_cells(cx, cz);
+        let step = 1 << lod.level;
+        let (nx, nz) = (xr.len(), zr.len());
+        let mut b = ChunkBuilder::new(self, xr.start, zr.start, nx, nz, step);
+
+        let xs = grid_lines(nx, step);
+        let zs = grid_lines(nz, step);
+        let inner_x = &xs[1 .. xs.len()-1];
+        let inner_z = &zs[1 .. zs.len()-1];
+        if inner_x.is_empty() || inner_z.is_empty()
+        {
+            // Chunk too small to stitch, emit a plain grid
+            for w in zs.windows(2) {
+                for v in xs.windows(2) {
+                    b.quad(v[0], w[0], v[1], w[1]);
+                }
+            }
+            return b.finish();
+        }
+
+        // Interior
+        for w in inner_z.windows(2) {
+            for v in inner_x.windows(2) {
+                b.quad(v[0], w[0], v[1], w[1]);
+            }
+        }
+
+        // Edge rings
+        let edge_step = |i: usize| ::std::cmp::max(step, 1 << lod.edges[i]);
+        let (x_first, x_last) = (inner_x[0], inner_x[inner_x.len()-1]);
+        let (z_first, z_last) = (inner_z[0], inner_z[inner_z.len()-1]);
+        // -Z
+        b.zip(
+            grid_lines(nx, edge_step(0)).into_iter().map(|x| (x, 0)).collect(),
+            inner_x.iter().map(|&x| (x, z_first)).collect(),
+            |p| p.0,
+            );
+        // +X
+        b.zip(
+            grid_lines(nz, edge_step(1)).into_iter().map(|z| (nx, z)).collect(),
+            inner_z.iter().map(|&z| (x_last, z)).collect(),
+            |p| p.1,
+            );
+        // +Z
+        b.zip(
+            grid_lines(nx, edge_step(2)).into_iter().map(|x| (x, nz)).collect(),
+            inner_x.iter().map(|&x| (x, z_last)).collect(),
+            |p| p.0,
+            );
+        // -X
+        b.zip(
+            grid_lines(nz, edge_step(3)).into_iter().map(|z| (0, z)).collect(),
+            inner_z.iter().map(|&z| (x_first, z)).collect(),
+            |p| p.1,
+            );
+        b.finish()
+    }
+
+    /// Render-space position of a heightmap pixel
+    fn vertex_position(&self, x: usize, z: usize) -> [f32; 3]
+    {
+        self.units.terrain_position(x as f32, z as f32, self.heights.get(x as isize, z as isize) as f32)
+    }
+
+    /// Texture used for the cell with its top-left (-X,-Z) corner at heightmap pixel `(x,z)` (see `cell_texture_index`)
+    pub fn cell_texture(&self, x: usize, z: usize) -> AtlasRect
+    {
+        let tex_id = cell_texture_index(&self.colours, x, z);
+        match self.textures.get(tex_id)
+        {
+        Some(v) => *v,
+        None => {
+            warn!("Terrain cell {},{} uses out of range texture {}", x, z, tex_id);
+            self.textures[0]
+            },
+        }
+    }
+
+    /// Atlas texture coordinate of a point within a terrain tile
+    ///
+    /// `fx` and `fz` are the position within the cell (0 to 1). The tile is laid out the same way as the map files: the
+    /// texture's first row is along the -Z edge, and the first column along the -X edge. Coordinates are inset by half
+    /// a texel to avoid bilinear filtering picking up the neighbouring atlas entry. V is flipped, as the atlas is
+    /// uploaded with its first row at V=1.
+    pub fn tile_uv(&self, tex: AtlasRect, fx: f32, fz: f32) -> [f32; 2]
+    {
+        let u = tex.x as f32 + 0.5 + (tex.dim as f32 - 1.) * fx;
+        let v = tex.y as f32 + 0.5 + (tex.dim as f32 - 1.) * fz;
+        [ u / self.atlas_size.0 as f32, 1.0 - v / self.atlas_size.1 as f32 ]
+    }
+
+    /// Smooth normal at a heightmap pixel, using central differences of the neighbouring heights
+    fn vertex_normal(&self, x: usize, z: usize) -> [f32; 3]
+    {
+        let h = &self.heights;
+        let (x, z) = (x as isize, z as isize);
+        let slope = self.units.height_step() / (2. * self.units.cell_size());
+        let dx = (h.get(x+1, z) as f32 - h.get(x-1, z) as f32) * slope;
+        let dz = (h.get(x, z+1) as f32 - h.get(x, z-1) as f32) * slope;
+        let len = (dx*dx + 1. + dz*dz).sqrt();
+        [ -dx / len, 1. / len, -dz / len ]
+    }
+}
+
+/// Index in the `.TEX` list of the texture for the cell with its top-left (-X,-Z) corner at heightmap pixel `(x,z)`
+///
+/// Each `.CLR` pixel textures the cell to the bottom-left (-X,+Z) of the matching heightmap pixel, so the cell at
+/// `(x,z)` is textured by `.CLR` pixel `(x+1,z)`. The `.CLR` values are plain indexes into the `.TEX` list, neither
+/// file has rotation or flip information.
+pub fn cell_texture_index(colours: &ByteGrid, x: usize, z: usize) -> usize
+{
+    colours.get(x as isize + 1, z as isize) as usize
+}
+
+/// Positions of grid lines from `0` to `n` (inclusive) at the given step, the last step may be short
+fn grid_lines(n: usize, step: usize) -> Vec<usize>
+{
+    let mut rv: Vec<_> = (0 .. n).filter(|v| v % step == 0).collect();
+    rv.push(n);
+    rv
+}
+
+/// Accumulates triangles (in chunk-local grid coordinates) into a `TerrainChunk`
+struct ChunkBuilder<'a>
+{
+    terrain: &'a Terrain,
+    x0: usize,
+    z0: usize,
+    nx: usize,
+    nz: usize,
+    step: usize,
+    /// Vertex lookup, keyed on grid position and texture block
+    vertex_map: HashMap<(usize, usize, usize, usize), u32>,
+    out: TerrainChunk,
+}
+impl<'a> ChunkBuilder<'a>
+{
+    fn new(terrain: &'a Terrain, x0: usize, z0: usize, nx: usize, nz: usize, step: usize) -> ChunkBuilder<'a>
+    {
+        ChunkBuilder {
+            terrain: terrain,
+            x0: x0, z0: z0,
+            nx: nx, nz: nz,
+            step: step,
+            vertex_map: HashMap::new(),
+            out: TerrainChunk {
+                positions: Vec::new(),
+                normals: Vec::new(),
+                tex_coords: Vec::new(),
+                indices: Vec::new(),
+                bounds: ([::std::f32::MAX; 3], [::std::f32::MIN; 3]),
+                },
+            }
+    }
+    fn finish(self) -> TerrainChunk {
+        self.out
+    }
+
+    /// Emit a quad with corners (x1,z1) and (x2,z2)
+    fn quad(&mut self, x1: usize, z1: usize, x2: usize, z2: usize)
+    {
+        // BottomLeft, TopRight, TopLeft
+        self.triangle([ (x1, z2), (x2, z1), (x1, z1) ]);
+        // BottomLeft, BottomRight, TopRight
+        self.triangle([ (x1, z2), (x2, z2), (x2, z1) ]);
+    }
+
+    /// Stitch between two parallel lines of points, `key` gives the position of a point along the lines
+    fn zip<F>(&mut self, outer: Vec<(usize,usize)>, inner: Vec<(usize,usize)>, key: F)
+    where
+        F: Fn(&(usize,usize)) -> usize
+    {
+        let (mut a, mut b) = (0, 0);
+        while a + 1 < outer.len() || b + 1 < inner.len()
+        {
+            if b + 1 == inner.len() || (a + 1 < outer.len() && key(&outer[a+1]) <= key(&inner[b+1])) {
+                self.triangle([ outer[a], outer[a+1], inner[b] ]);
+                a += 1;
+            }
+            else {
+                self.triangle([ outer[a], inner[b+1], inner[b] ]);
+                b += 1;
+            }
+        }
+    }
+
+    fn triangle(&mut self, mut pts: [(usize,usize); 3])
+    {
+        // Match the winding used for `quad` (negative area in X/Z)
+        let area = (pts[1].0 as isize - pts[0].0 as isize) * (pts[2].1 as isize - pts[0].1 as isize)
+            - (pts[1].1 as isize - pts[0].1 as isize) * (pts[2].0 as isize - pts[0].0 as isize);
+        if area > 0 {
+            pts.swap(1, 2);
+        }
+
+        // Texture block containing this triangle (using the centroid)
+        let step = self.step;
+        let block = |v: usize, n: usize| ::std::cmp::min(v / step * step, (n - 1) / step * step);
+        let bx = block((pts[0].0 + pts[1].0 + pts[2].0) / 3, self.nx);
+        let bz = block((pts[0].1 + pts[1].1 + pts[2].1) / 3, self.nz);
+
+        for &(x, z) in &pts
+        {
+            let idx = match self.vertex_map.get(&(x, z, bx, bz)).cloned()
+                {
+                Some(v) => v,
+                None => {
+                    let v = self.add_vertex(x, z, bx, bz);
+                    self.vertex_map.insert( (x, z, bx, bz), v );
+                    v
+                    },
+                };
+            self.out.indices.push(idx);
+        }
+    }
+
+    fn add_vertex(&mut self, x: usize, z: usize, bx: usize, bz: usize) -> u32
+    {
+        let t = self.terrain;
+        let (gx, gz) = (self.x0 + x, self.z0 + z);
+        let tex = t.cell_texture(self.x0 + bx, self.z0 + bz);
+        // Position within the texture block (clamped, stitching triangles can extend past the block)
+        let bw = ::std::cmp::min(self.step, self.nx - bx) as f32;
+        let bh = ::std::cmp::min(self.step, self.nz - bz) as f32;
+        let fx = ((x as f32 - bx as f32) / bw).max(0.).min(1.);
+        let fz = ((z as f32 - bz as f32) / bh).max(0.).min(1.);
+
+        let p = t.vertex_position(gx, gz);
+        for i in 0 .. 3
+        {
+            self.out.bounds.0[i] = self.out.bounds.0[i].min(p[i]);
+            self.out.bounds.1[i] = self.out.bounds.1[i].max(p[i]);
+        }
+        self.out.positions.push(p);
+        self.out.normals.push( t.vertex_normal(gx, gz) );
+        self.out.tex_coords.push( t.tile_uv(tex, fx, fz) );
+        (self.out.positions.len() - 1) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// OpenGL-style perspective projection (90 degree FOV, square, near 0.1, far 100), looking down -Z from the origin
+    fn projection() -> [[f32; 4]; 4]
+    {
+        let (near, far) = (0.1, 100.);
+        [
+            [1., 0., 0., 0.],
+            [0., 1., 0., 0.],
+            [0., 0., (far + near) / (near - far), -1.],
+            [0., 0., 2. * far * near / (near - far), 0.],
+            ]
+    }
+    fn cube(c: [f32; 3], r: f32) -> ([f32; 3], [f32; 3])
+    {
+        ([c[0] - r, c[1] - r, c[2] - r], [c[0] + r, c[1] + r, c[2] + r])
+    }
+
+    /// Terrain with 2x2 chunks and an uneven heightmap
+    fn test_terrain() -> Terrain
+    {
+        let dim = 2 * CHUNK_SIZE + 1;
+        let heights: Vec<u8> = (0 .. dim * dim).map(|i| ((i % dim) * 37 + (i / dim) * 91 ^ (i % 7) * 13) as u8).collect();
+        let heights = ByteGrid::from_reader(&heights[..]).unwrap();
+        let colours = ByteGrid::from_reader(&vec![0; dim * dim][..]).unwrap();
+        Terrain::new(heights, colours, vec![ AtlasRect { x: 0, y: 0, dim: 8 } ]).unwrap()
+    }
+
+    /// Triangle edges and vertices of a chunk that lie on the line where coordinate `axis` is `at`
+    ///
+    /// Edges are returned as the range they cover along the other horizontal axis, vertices as (along, height).
+    fn boundary(chunk: &TerrainChunk, axis: usize, at: f32) -> (Vec<(f32, f32)>, Vec<(f32, f32)>)
+    {
+        let along = 2 - axis;
+        let mut edges = Vec::new();
+        let mut verts = Vec::new();
+        for tri in chunk.indices.chunks(3)
+        {
+            for i in 0 .. 3
+            {
+                let p = chunk.positions[tri[i] as usize];
+                let q = chunk.positions[tri[(i + 1) % 3] as usize];
+                if p[axis] == at {
+                    verts.push( (p[along], p[1]) );
+                }
+                if p[axis] == at && q[axis] == at {
+                    edges.push( (p[along].min(q[along]), p[along].max(q[along])) );
+                }
+            }
+        }
+        let cmp = |a: &(f32, f32), b: &(f32, f32)| a.partial_cmp(b).unwrap();
+        edges.sort_by(&cmp);
+        verts.sort_by(&cmp);
+        verts.dedup();
+        (edges, verts)
+    }
+
+    /// Total area covered by a chunk's triangles (in the X/Z plane)
+    fn covered_area(chunk: &TerrainChunk) -> f32
+    {
+        chunk.indices.chunks(3)
+            .map(|tri| {
+                let p: Vec<_> = tri.iter().map(|&i| chunk.positions[i as usize]).collect();
+                ((p[1][0] - p[0][0]) * (p[2][2] - p[0][2]) - (p[1][2] - p[0][2]) * (p[2][0] - p[0][0])).abs() / 2.
+                })
+            .sum()
+    }
+
+    /// Neighbouring chunks at every pair of levels share exactly the same edge vertices and edge segments (so there
+    /// are no cracks or T-junctions), and each chunk covers its whole area.
+    #[test]
+    fn stitched_edges_match()
+    {
+        let t = test_terrain();
+        let chunk_len = CHUNK_SIZE as f32 * t.units().cell_size();
+        // Boundary between the chunks, along X and along Z
+        let mid = t.units().terrain_position(CHUNK_SIZE as f32, CHUNK_SIZE as f32, 0.);
+        for a in 0 .. MAX_LOD + 1
+        {
+            for b in 0 .. MAX_LOD + 1
+            {
+                let lod = |level: u8, edge: usize, other: u8| {
+                    let mut edges = [level; 4];
+                    edges[edge] = other;
+                    ChunkLod { level: level, edges: edges }
+                    };
+                // (axis, near chunk, far chunk), the near chunk's +X/+Z edge meets the far chunk's -X/-Z edge
+                let pairs = [
+                    (0, t.build_chunk(0, 0, lod(a, 1, b)), t.build_chunk(1, 0, lod(b, 3, a))),
+                    (2, t.build_chunk(0, 0, lod(a, 2, b)), t.build_chunk(0, 1, lod(b, 0, a))),
+                    ];
+                for &(axis, ref near, ref far) in &pairs
+                {
+                    let (near_edges, near_verts) = boundary(near, axis, mid[axis]);
+                    let (far_edges, far_verts) = boundary(far, axis, mid[axis]);
+                    assert!(!near_edges.is_empty(), "No edge on axis {} for levels {}/{}", axis, a, b);
+                    assert_eq!(near_verts, far_verts, "Edge vertices differ on axis {} for levels {}/{}", axis, a, b);
+                    assert_eq!(near_edges, far_edges, "Edge segments differ on axis {} for levels {}/{}", axis, a, b);
+                    // Segments cover the edge with no gaps or overlaps
+                    for w in near_edges.windows(2) {
+                        assert_eq!(w[0].1, w[1].0, "Gap in edge on axis {} for levels {}/{}", axis, a, b);
+                    }
+                    assert_eq!(near_edges[near_edges.len()-1].1 - near_edges[0].0, chunk_len);
+
+                    for c in &[near, far]
+                    {
+                        let area = covered_area(c);
+                        assert!((area - chunk_len * chunk_len).abs() < 1e-3, "Chunk area {} for levels {}/{}", area, a, b);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Every cell of a full detail chunk samples the atlas entry of the texture its `.CLR` pixel names, with the tile's
+    /// first row along the cell's -Z edge and first column along its -X edge
+    #[test]
+    fn chunk_textures_match_clr()
+    {
+        let dim = CHUNK_SIZE + 1;
+        let heights: Vec<u8> = (0 .. dim * dim).map(|i| (i * 7 % 251) as u8).collect();
+        let clr: Vec<u8> = (0 .. dim * dim).map(|i| ((i % dim) * 3 + (i / dim) * 5 + i / 7) as u8 % 4).collect();
+        // Different sizes and both axes used, so a swapped or flipped mapping lands in the wrong entry
+        let rects = vec![
+            AtlasRect { x: 0, y: 0, dim: 8 },
+            AtlasRect { x: 8, y: 0, dim: 8 },
+            AtlasRect { x: 0, y: 8, dim: 16 },
+            AtlasRect { x: 16, y: 0, dim: 4 },
+            ];
+        let (atlas_w, atlas_h) = (20., 24.);
+        let t = Terrain::new(ByteGrid::from_reader(&heights[..]).unwrap(), ByteGrid::from_reader(&clr[..]).unwrap(), rects.clone()).unwrap();
+        let chunk = t.build_chunk(0, 0, ChunkLod::full_detail());
+
+        let mut cells = ::std::collections::HashSet::new();
+        for tri in chunk.indices.chunks(3)
+        {
+            // Heightmap pixel of each vertex, and the cell containing the triangle
+            let grid: Vec<_> = tri.iter()
+                .map(|&i| {
+                    let (x, z) = t.units().terrain_cell(chunk.positions[i as usize]);
+                    (x.round() as usize, z.round() as usize)
+                    })
+                .collect();
+            let x = grid.iter().map(|g| g.0).min().unwrap();
+            let z = grid.iter().map(|g| g.1).min().unwrap();
+            cells.insert( (x, z) );
+
+            let tex_id = clr[z * dim + x + 1] as usize;
+            let r = rects[tex_id];
+            for (&i, &(gx, gz)) in tri.iter().zip(&grid)
+            {
+                let uv = chunk.tex_coords[i as usize];
+                // Back to atlas texels, undoing the V flip
+                let (u, v) = (uv[0] * atlas_w, (1. - uv[1]) * atlas_h);
+                let expected_u = r.x as f32 + 0.5 + (r.dim - 1) as f32 * (gx - x) as f32;
+                let expected_v = r.y as f32 + 0.5 + (r.dim - 1) as f32 * (gz - z) as f32;
+                assert!((u - expected_u).abs() < 1e-3 && (v - expected_v).abs() < 1e-3,
+                    "Cell {},{} corner {},{} at texel {},{}, expected texture {} at {},{}", x, z, gx, gz, u, v, tex_id, expected_u, expected_v);
+            }
+        }
+        assert_eq!(cells.len(), CHUNK_SIZE * CHUNK_SIZE);
+    }
+
+    #[test]
+    fn frustum_culling()
+    {
+        let clip = projection();
+        assert!( bounds_in_frustum(&clip, &cube([0., 0., -10.], 1.)) );
+        // Straddling the edge of the view
+        assert!( bounds_in_frustum(&clip, &cube([10.5, 0., -10.], 1.)) );
+        // Containing the camera
+        assert!( bounds_in_frustum(&clip, &cube([0., 0., 0.], 5.)) );
+        // Behind, to the side, above, and past the far plane
+        assert!( !bounds_in_frustum(&clip, &cube([0., 0., 10.], 1.)) );
+        assert!( !bounds_in_frustum(&clip, &cube([20., 0., -10.], 1.)) );
+        assert!( !bounds_in_frustum(&clip, &cube([0., 20., -10.], 1.)) );
+        assert!( !bounds_in_frustum(&clip, &cube([0., 0., -200.], 1.)) );
+    }
+}
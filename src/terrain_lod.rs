@@ -0,0 +1,134 @@
+//!
+//! Terrain chunk entities and the level-of-detail selection system
+//!
+//! Chunks outside the camera's view are culled by removing their `MeshHandle` component (Amethyst 0.6 draws every
+//! entity with a mesh), the mesh is kept in `TerrainChunkRef` until the chunk is visible again.
+use amethyst::ecs;
+use amethyst::ecs::Join;
+use amethyst::core::transform::Transform;
+use amethyst::core::cgmath::SquareMatrix;
+use amethyst::renderer as a_renderer;
+
+use terrain;
+
+/// Marks an entity as a terrain chunk, and records its current mesh
+pub struct TerrainChunkRef
+{
+    pub cx: usize,
+    pub cz: usize,
+    pub lod: terrain::ChunkLod,
+    /// Mesh for `lod` (also in the entity's `MeshHandle` while the chunk is visible)
+    pub mesh: a_renderer::MeshHandle,
+    /// Bounding box of `mesh`
+    pub bounds: ([f32; 3], [f32; 3]),
+}
+impl TerrainChunkRef
+{
+    /// Generate and upload the mesh for a chunk
+    pub fn build(loader: &::amethyst::assets::Loader, storage: &::amethyst::assets::AssetStorage<a_renderer::Mesh>, terrain: &terrain::Terrain, cx: usize, cz: usize, lod: terrain::ChunkLod) -> TerrainChunkRef
+    {
+        let chunk = terrain.build_chunk(cx, cz, lod);
+        TerrainChunkRef {
+            cx: cx,
+            cz: cz,
+            lod: lod,
+            mesh: chunk_mesh(loader, storage, &chunk),
+            bounds: chunk.bounds,
+            }
+    }
+}
+impl ecs::Component for TerrainChunkRef
+{
+    type Storage = ecs::VecStorage<Self>;
+}
+
+/// Upload a generated chunk as a mesh
+fn chunk_mesh(loader: &::amethyst::assets::Loader, storage: &::amethyst::assets::AssetStorage<a_renderer::Mesh>, chunk: &terrain::TerrainChunk)
+    -> a_renderer::MeshHandle
+{
+    // NOTE: Amethyst 0.6 meshes don't take an index buffer, so the index list has to be expanded here.
+    let m2: a_renderer::ComboMeshCreator = (
+        chunk.unindexed(&chunk.positions).map(|p| a_renderer::Separate::<a_renderer::Position>::new(p)).collect::<Vec<_>>(),
+        None,   // Colours
+        Some(chunk.unindexed(&chunk.tex_coords).map(|v| a_renderer::Separate::new(v)).collect()),   // Texture coords (needed)
+        Some(chunk.unindexed(&chunk.normals).map(|v| a_renderer::Separate::new(v)).collect()),   // Normals
+        None,   // TODO: Tangents?
+        ).into();
+    loader.load_from_data(m2.into(), (), storage)
+}
+
+/// Picks a detail level for each terrain chunk based on the distance from the camera, regenerates chunk meshes when
+/// their level (or a neighbour's) changes, and culls chunks outside the view frustum.
+pub struct TerrainLodSystem;
+impl<'s> ecs::System<'s> for TerrainLodSystem
+{
+    type SystemData = (
+        ecs::Entities<'s>,
+        ecs::Fetch<'s, terrain::Terrain>,
+        ecs::Fetch<'s, ::amethyst::assets::Loader>,
+        ecs::Fetch<'s, ::amethyst::assets::AssetStorage<a_renderer::Mesh>>,
+        ecs::ReadStorage<'s, a_renderer::Camera>,
+        ecs::ReadStorage<'s, Transform>,
+        ecs::WriteStorage<'s, TerrainChunkRef>,
+        ecs::WriteStorage<'s, a_renderer::MeshHandle>,
+        );
+    fn run(&mut self, (entities, terrain, loader, mesh_storage, cam, transform, mut chunks, mut meshes): Self::SystemData)
+    {
+        let (cam_pos, clip) = match (&cam, &transform).join().next()
+            {
+            Some((c, t)) => {
+                let clip: [[f32; 4]; 4] = match t.0.invert()
+                    {
+                    Some(view) => (c.proj * view).into(),
+                    None => return,
+                    };
+                ([t.0.w.x, t.0.w.y, t.0.w.z], clip)
+                },
+            None => return,
+            };
+
+        // Select the level for every chunk first, so that neighbours can be stitched
+        let n = terrain.chunk_count();
+        let mut levels = vec![0u8; n * n];
+        for cz in 0 .. n
+        {
+            for cx in 0 .. n
+            {
+                let c = terrain.chunk_centre(cx, cz);
+                let d = [c[0] - cam_pos[0], c[1] - cam_pos[1], c[2] - cam_pos[2]];
+                let dist = (d[0]*d[0] + d[1]*d[1] + d[2]*d[2]).sqrt();
+                levels[cz * n + cx] = terrain.clamp_lod(cx, cz, terrain::lod_for_distance(dist));
+            }
+        }
+
+        for (ent, chunk) in (&*entities, &mut chunks).join()
+        {
+            let (cx, cz) = (chunk.cx, chunk.cz);
+            let level = levels[cz * n + cx];
+            let neighbour = |ok: bool, x: usize, z: usize| if ok { levels[z * n + x] } else { level };
+            let lod = terrain::ChunkLod {
+                level: level,
+                edges: [
+                    neighbour(cz > 0    , cx, cz.wrapping_sub(1)),
+                    neighbour(cx + 1 < n, cx + 1, cz),
+                    neighbour(cz + 1 < n, cx, cz + 1),
+                    neighbour(cx > 0    , cx.wrapping_sub(1), cz),
+                    ],
+                };
+            let changed = lod != chunk.lod;
+            if changed
+            {
+                *chunk = TerrainChunkRef::build(&loader, &mesh_storage, &terrain, cx, cz, lod);
+            }
+
+            if terrain::bounds_in_frustum(&clip, &chunk.bounds) {
+                if changed || meshes.get(ent).is_none() {
+                    meshes.insert(ent, chunk.mesh.clone());
+                }
+            }
+            else {
+                meshes.remove(ent);
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+//!
+//! Conversion from the original game's units into render space
+//!
+//! Render space is Y-up, with the origin at the centre of the heightmap and one unit covering 8 heightmap cells.
+//!
+//! Original units (see `docs/datafile_notes/levels.md`):
+//! - Heightmap (`.RAW`): one pixel per cell horizontally, heights are a byte with 256 steps per render unit.
+//! - Entity placements (`.DEF`): 12.20 fixed point, the integer part being one heightmap cell. X and Z are offset by the
+//!   size of the map.
+//! - Models (`.BIN`): raw vertex values multiplied by the file's 9.23 scale factor, 100 model units per render unit.
+//!
+//! Apart from the model scale factor, these are guesses that line the terrain, models and entities up. They are kept
+//! here so the guesswork only happens in one place.
+use datafile::Fixed;
+use datafile::fixed::Frac20;
+
+/// Render units per heightmap cell
+const RENDER_PER_CELL: f32 = 1. / 8.;
+/// Render units per heightmap height step
+const RENDER_PER_HEIGHT: f32 = 1. / 256.;
+/// Render units per (scaled) model unit
+const RENDER_PER_MODEL_UNIT: f32 = 1. / 100.;
+
+/// Unit conversions for a level (the origin depends on the heightmap size)
+#[derive(Copy,Clone,Debug)]
+pub struct WorldUnits
+{
+    map_dim: usize,
+}
+impl WorldUnits
+{
+    /// `map_dim` is the number of pixels along each side of the heightmap
+    pub fn new(map_dim: usize) -> WorldUnits
+    {
+        WorldUnits {
+            map_dim: map_dim,
+        }
+    }
+
+    /// Horizontal distance between heightmap pixels
+    pub fn cell_size(&self) -> f32 {
+        RENDER_PER_CELL
+    }
+    /// Vertical distance of one heightmap step
+    pub fn height_step(&self) -> f32 {
+        RENDER_PER_HEIGHT
+    }
+
+    /// Render-space position of a heightmap pixel with the given height
+    pub fn terrain_position(&self, x: f32, z: f32, height: f32) -> [f32; 3]
+    {
+        let ofs = (self.map_dim / 2) as f32;
+        [
+            (x - ofs) * RENDER_PER_CELL,
+            height * RENDER_PER_HEIGHT,
+            (z - ofs) * RENDER_PER_CELL,
+            ]
+    }
+    /// Convert a render-space position to (fractional) heightmap pixel coordinates
+    pub fn terrain_cell(&self, pos: [f32; 3]) -> (f32, f32)
+    {
+        let ofs = (self.map_dim / 2) as f32;
+        (pos[0] / RENDER_PER_CELL + ofs, pos[2] / RENDER_PER_CELL + ofs)
+    }
+
+    /// Render-space position of an entity placement
+    pub fn entity_position(&self, x: Fixed<Frac20>, y: Fixed<Frac20>, z: Fixed<Frac20>) -> [f32; 3]
+    {
+        let dim = self.map_dim as f32;
+        // Entity Y is in cells, not height steps
+        let y_cells = y.to_f32() * RENDER_PER_CELL / RENDER_PER_HEIGHT;
+        self.terrain_position(x.to_f32() + dim, z.to_f32() + dim, y_cells)
+    }
+
+    /// Render-space offset of a model-space position (as returned by `Model::vertex_position`)
+    ///
+    /// Models don't depend on the level, so this doesn't need an instance.
+    pub fn model_position(v: [f32; 3]) -> [f32; 3]
+    {
+        [ v[0] * RENDER_PER_MODEL_UNIT, v[1] * RENDER_PER_MODEL_UNIT, v[2] * RENDER_PER_MODEL_UNIT ]
+    }
+}